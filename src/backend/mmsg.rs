@@ -0,0 +1,394 @@
+//! The Linux `recvmmsg`/`sendmmsg` batching backend
+
+use crate::backend::sys::{self, SOCKADDR_STORAGE_LEN};
+use crate::backend::{self, Heartbeat, PACKET_SIZE_MAX};
+use crate::config::Config;
+use crate::error;
+use crate::error::Error;
+use crate::log;
+use crate::relay::Relay;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{mem, ptr, slice};
+
+/// The maximum size of a coalesced GRO super-packet
+const SUPER_PACKET_SIZE_MAX: usize = 65535;
+
+/// A suitably aligned buffer for a single UDP offload control message
+type ControlBuffer = [u64; 4];
+
+/// A batch of receive buffers
+struct RecvBatch {
+    /// The packet buffers
+    buffers: Vec<Vec<u8>>,
+    /// The source addresses
+    names: Vec<libc::sockaddr_storage>,
+    /// The I/O vectors pointing into `buffers`
+    #[allow(dead_code, reason = "The I/O vectors are only accessed by the kernel via the message headers")]
+    iovecs: Vec<libc::iovec>,
+    /// The control message buffers for the GRO segment size
+    #[allow(dead_code, reason = "The control buffers are only accessed via the message headers")]
+    controls: Vec<ControlBuffer>,
+    /// The message headers pointing into `names`, `iovecs` and `controls`
+    headers: Vec<libc::mmsghdr>,
+    /// Whether UDP GRO is enabled on the socket
+    gro: bool,
+}
+impl RecvBatch {
+    /// Creates a new receive batch with the given size
+    pub fn new(size: usize, gro: bool) -> Self {
+        // Coalesced super-packets need larger buffers
+        let buffer_len = match gro {
+            true => SUPER_PACKET_SIZE_MAX,
+            false => PACKET_SIZE_MAX,
+        };
+        let mut buffers = vec![vec![0; buffer_len]; size];
+        let mut names = vec![sys::sockaddr_storage(); size];
+        let mut controls = vec![ControlBuffer::default(); size];
+        let mut iovecs: Vec<_> = (buffers.iter_mut())
+            .map(|buffer| libc::iovec { iov_base: buffer.as_mut_ptr().cast(), iov_len: buffer_len })
+            .collect();
+
+        // Setup the message headers; the vectors are never resized, so the pointers remain valid
+        let headers = (names.iter_mut().zip(iovecs.iter_mut()).zip(controls.iter_mut()))
+            .map(|((name, iovec), control)| {
+                // Safety: `mmsghdr` is a plain C struct where all-zero is a valid bit pattern
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (name as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                if gro {
+                    // Receive the segment size as control message
+                    header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+                }
+                header
+            })
+            .collect();
+        Self { buffers, names, iovecs, controls, headers, gro }
+    }
+
+    /// Receives a batch of packets and blocks until at least one packet is available
+    pub fn recv(&mut self, fd: RawFd) -> Result<usize, io::Error> {
+        // Reset the address and control lengths as they are overwritten by the kernel
+        for header in self.headers.iter_mut() {
+            header.msg_hdr.msg_namelen = SOCKADDR_STORAGE_LEN;
+            if self.gro {
+                header.msg_hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+            }
+        }
+
+        // Receive the packets
+        let vlen = self.headers.len() as libc::c_uint;
+        // Safety: All headers point to valid and live buffers within `self`
+        let received =
+            unsafe { libc::recvmmsg(fd, self.headers.as_mut_ptr(), vlen, libc::MSG_WAITFORONE, ptr::null_mut()) };
+        match usize::try_from(received) {
+            Ok(received) => Ok(received),
+            Err(_) => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Iterates over the first `count` received packets, their source addresses and their GRO segment size if any
+    pub fn packets(&self, count: usize) -> impl Iterator<Item = (&[u8], Option<SocketAddr>, Option<usize>)> {
+        let packets = self.names.iter().zip(self.headers.iter()).take(count).enumerate();
+        packets.map(|(index, (name, header))| {
+            (self.packet(index), sys::to_socket_addr(name), Self::segment_size(&header.msg_hdr))
+        })
+    }
+
+    /// Gets the received packet at the given index
+    pub fn packet(&self, index: usize) -> &[u8] {
+        let (Some(buffer), Some(header)) = (self.buffers.get(index), self.headers.get(index)) else {
+            // This should never happen as the index is always within the batch bounds
+            return &[];
+        };
+        buffer.get(..header.msg_len as usize).unwrap_or_default()
+    }
+
+    /// Gets the GRO segment size from the control messages of the given header
+    fn segment_size(header: &libc::msghdr) -> Option<usize> {
+        // Safety: The header either has no control buffer, or a valid control buffer with the length set by the kernel
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(header) };
+        while !cmsg.is_null() {
+            // Safety: The pointer is not null and points into the control buffer
+            let cmsg_ = unsafe { &*cmsg };
+            if cmsg_.cmsg_level == libc::SOL_UDP && cmsg_.cmsg_type == libc::UDP_GRO {
+                // Safety: The kernel passes the segment size as `int`
+                let segment_size = unsafe { libc::CMSG_DATA(cmsg).cast::<libc::c_int>().read_unaligned() };
+                return usize::try_from(segment_size).ok().filter(|segment_size| *segment_size > 0);
+            }
+
+            // Safety: The header and the current control message are valid
+            cmsg = unsafe { libc::CMSG_NXTHDR(header, cmsg) };
+        }
+        None
+    }
+}
+
+/// A queue of packets to send
+struct SendQueue {
+    /// The destination addresses
+    names: Vec<libc::sockaddr_storage>,
+    /// The I/O vectors pointing into the receive buffers
+    iovecs: Vec<libc::iovec>,
+    /// The control message buffers for the GSO segment size
+    controls: Vec<ControlBuffer>,
+    /// The GSO segment sizes of the queued packets, if any
+    segment_sizes: Vec<Option<u16>>,
+    /// The message headers pointing into `names`, `iovecs` and `controls`
+    headers: Vec<libc::mmsghdr>,
+    /// The amount of queued packets
+    len: usize,
+    /// Whether UDP GSO is available
+    gso: bool,
+}
+impl SendQueue {
+    /// Creates a new send queue with the given capacity
+    pub fn new(capacity: usize, gso: bool) -> Self {
+        let mut names = vec![sys::sockaddr_storage(); capacity];
+        let mut iovecs = vec![libc::iovec { iov_base: ptr::null_mut(), iov_len: 0 }; capacity];
+        let controls = vec![ControlBuffer::default(); capacity];
+        let segment_sizes = vec![None; capacity];
+
+        // Setup the message headers; the vectors are never resized, so the pointers remain valid
+        let headers = (names.iter_mut().zip(iovecs.iter_mut()))
+            .map(|(name, iovec)| {
+                // Safety: `mmsghdr` is a plain C struct where all-zero is a valid bit pattern
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (name as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+        Self { names, iovecs, controls, segment_sizes, headers, len: 0, gso }
+    }
+
+    /// Queues a packet for sending and flushes the queue if it is full
+    ///
+    /// # GSO
+    /// If a segment size is given, the packet is a coalesced super-packet that is segmented by the kernel.
+    ///
+    /// # Safety
+    /// The packet must remain valid until the queue is flushed.
+    pub unsafe fn push(&mut self, fd: RawFd, packet: &[u8], destination: &SocketAddr, segment_size: Option<u16>) {
+        let index = self.len;
+        let (Some(name), Some(iovec), Some(control), Some(segment_size_), Some(header)) = (
+            self.names.get_mut(index),
+            self.iovecs.get_mut(index),
+            self.controls.get_mut(index),
+            self.segment_sizes.get_mut(index),
+            self.headers.get_mut(index),
+        ) else {
+            // This should never happen as the queue is flushed once it is full
+            return;
+        };
+
+        // Queue the packet
+        header.msg_hdr.msg_namelen = sys::from_socket_addr(destination, name);
+        iovec.iov_base = packet.as_ptr().cast_mut().cast();
+        iovec.iov_len = packet.len();
+        *segment_size_ = segment_size;
+        (header.msg_hdr.msg_control, header.msg_hdr.msg_controllen) = match segment_size {
+            Some(segment_size) => Self::segment_control(control, segment_size),
+            None => (ptr::null_mut(), 0),
+        };
+        self.len = self.len.saturating_add(1);
+
+        // Flush the queue if it is full
+        if self.len == self.headers.len() {
+            self.flush(fd);
+        }
+    }
+
+    /// Sends all queued packets
+    pub fn flush(&mut self, fd: RawFd) {
+        let mut offset = 0;
+        while offset < self.len {
+            // Send the remaining packets
+            let Some(headers) = self.headers.get_mut(offset..self.len) else {
+                // This should never happen as the offset is always within the queue bounds
+                break;
+            };
+            let vlen = headers.len() as libc::c_uint;
+            // Safety: All queued headers point to valid and live buffers
+            let sent = unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), vlen, 0) };
+
+            // Retry or skip the first packet on error
+            let Ok(sent) = usize::try_from(sent) else {
+                let error = io::Error::last_os_error();
+                if error.kind() != ErrorKind::Interrupted {
+                    // Handle the failed packet
+                    self.fallback(fd, offset, error);
+                    offset = offset.saturating_add(1);
+                }
+                continue;
+            };
+            offset = offset.saturating_add(sent);
+        }
+        self.len = 0;
+    }
+
+    /// Handles a failed packet by sending it segment-wise if it was a GSO packet, or by logging the error
+    fn fallback(&mut self, fd: RawFd, index: usize, error: io::Error) {
+        let destination = self.names.get(index).and_then(sys::to_socket_addr);
+        let (Some(name), Some(iovec), Some(header), Some(Some(segment_size))) =
+            (self.names.get(index), self.iovecs.get(index), self.headers.get(index), self.segment_sizes.get(index))
+        else {
+            // This is not necessarily fatal, but worth a warning
+            log!(warn: error!(with: error, "Failed to forward packet to {destination:?}"));
+            return;
+        };
+
+        // The kernel or the device cannot segment the packet, so disable GSO
+        log!(info: error!(with: error, "UDP GSO failed, falling back to unsegmented sends"));
+        self.gso = false;
+
+        // Send the segments one by one
+        // Safety: The I/O vector points to a queued packet which is still valid
+        let packet = unsafe { slice::from_raw_parts(iovec.iov_base.cast::<u8>().cast_const(), iovec.iov_len) };
+        let name_ptr = (name as *const libc::sockaddr_storage).cast();
+        for segment in packet.chunks(usize::from(*segment_size)) {
+            // Safety: The segment and the address are valid for the duration of the call
+            let sent = unsafe {
+                libc::sendto(fd, segment.as_ptr().cast(), segment.len(), 0, name_ptr, header.msg_hdr.msg_namelen)
+            };
+            if sent < 0 {
+                // This is not necessarily fatal, but worth a warning
+                let error = io::Error::last_os_error();
+                log!(warn: error!(with: error, "Failed to forward packet to {destination:?}"));
+            }
+        }
+    }
+
+    /// Writes a `UDP_SEGMENT` control message into the given buffer and returns the control pointer and length
+    fn segment_control(control: &mut ControlBuffer, segment_size: u16) -> (*mut libc::c_void, usize) {
+        // Safety: `CMSG_SPACE` is a pure computation
+        let space = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as libc::c_uint) } as usize;
+
+        // Setup a temporary header to use the `CMSG_*` helpers
+        // Safety: `msghdr` is a plain C struct where all-zero is a valid bit pattern
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_control = control.as_mut_ptr().cast();
+        header.msg_controllen = space as _;
+
+        // Safety: The control buffer is large enough for a single `u16` control message
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as libc::c_uint) as _;
+            libc::CMSG_DATA(cmsg).cast::<u16>().write_unaligned(segment_size);
+        }
+        (header.msg_control, space)
+    }
+}
+
+/// Probes whether `recvmmsg` is supported by the kernel
+pub fn is_supported(socket: &UdpSocket) -> bool {
+    // Safety: An empty message vector is never accessed by the kernel
+    let result = unsafe { libc::recvmmsg(socket.as_raw_fd(), ptr::null_mut(), 0, libc::MSG_DONTWAIT, ptr::null_mut()) };
+    let unsupported = result < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::ENOSYS);
+    if unsupported {
+        // Log the fallback
+        log!(info: error!("recvmmsg is not supported, falling back to the portable backend"));
+    }
+    !unsupported
+}
+
+/// Enables UDP GRO on the socket and returns whether it is supported
+fn enable_gro(fd: RawFd) -> bool {
+    let Err(e) = sys::setsockopt(fd, libc::SOL_UDP, libc::UDP_GRO, 1) else {
+        // GRO has been enabled
+        return true;
+    };
+    log!(info: error!(with: e, "UDP GRO is not supported, falling back to unsegmented receives"));
+    false
+}
+
+/// Probes whether UDP GSO is supported by the kernel
+fn supports_gso(fd: RawFd) -> bool {
+    // A segment size of zero disables socket-wide segmentation, but is rejected by kernels without GSO support
+    let Err(e) = sys::setsockopt(fd, libc::SOL_UDP, libc::UDP_SEGMENT, 0) else {
+        // GSO is supported
+        return true;
+    };
+    log!(info: error!(with: e, "UDP GSO is not supported, falling back to unsegmented sends"));
+    false
+}
+
+/// Receives and forwards packets in batches
+pub fn run(
+    socket: &UdpSocket,
+    config: &Config,
+    relay: &Mutex<Relay>,
+    (stop, heartbeat): (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    let fd = socket.as_raw_fd();
+    let batch_size = config.WGPROXY_BATCH_SIZE;
+    let offload = config.WGPROXY_OFFLOAD;
+
+    // Setup I/O state
+    let mut batch = RecvBatch::new(batch_size, offload && enable_gro(fd));
+    let mut queue = SendQueue::new(batch_size, offload && supports_gso(fd));
+    let mut destinations = Vec::new();
+    let mut segments: Vec<(Range<usize>, SocketAddr)> = Vec::new();
+    let mut routes: Vec<(usize, Range<usize>, SocketAddr, Option<u16>)> = Vec::new();
+    'network_loop: while !stop.load(Ordering::Relaxed) {
+        heartbeat.beat();
+
+        // Receive next inbound packets
+        let count = match batch.recv(fd) {
+            Ok(count) => count,
+            Err(e) if backend::is_wakeup(&e) => continue 'network_loop,
+            Err(e) => return Err(error!(with: e, "Failed to receive inbound packets")),
+        };
+
+        // Route the packets; the relay is only locked for routing, so that the I/O of all workers runs in parallel
+        let mut relay = Relay::lock(relay)?;
+        for (index, (packet, source_addr, segment_size)) in batch.packets(count).enumerate() {
+            let Some(source_addr) = source_addr else {
+                // This should never happen for UDP sockets
+                continue;
+            };
+
+            // Route each segment of a coalesced super-packet individually
+            let segment_size = segment_size.unwrap_or(packet.len()).max(1);
+            for (segment_index, segment) in packet.chunks(segment_size).enumerate() {
+                let start = segment_index.saturating_mul(segment_size);
+                let range = start..start.saturating_add(segment.len());
+                relay.handle(segment, &source_addr, &mut destinations)?;
+                segments.extend(destinations.drain(..).map(|destination| (range.clone(), destination)));
+            }
+
+            // Send the super-packet at once if all segments go to the same single destination
+            let segment_count = packet.len().div_ceil(segment_size);
+            let gso_segment_size = u16::try_from(segment_size).ok().filter(|_| queue.gso && segment_count > 1);
+            if let (Some(gso_segment_size), Some((_, destination))) = (gso_segment_size, segments.first())
+                && segments.len() == segment_count
+                && segments.iter().all(|(_, destination_)| destination_ == destination)
+            {
+                routes.push((index, 0..packet.len(), *destination, Some(gso_segment_size)));
+                segments.clear();
+                continue;
+            }
+
+            // Send the segments to all destinations
+            routes.extend(segments.drain(..).map(|(range, destination)| (index, range, destination, None)));
+        }
+        drop(relay);
+
+        // Forward the packets
+        for (index, range, destination, segment_size) in routes.drain(..) {
+            let packet = batch.packet(index).get(range).unwrap_or_default();
+            // Safety: The receive buffers are not reused before the queue is flushed below
+            unsafe { queue.push(fd, packet, &destination, segment_size) };
+        }
+        queue.flush(fd);
+    }
+    Ok(())
+}
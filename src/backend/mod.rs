@@ -0,0 +1,135 @@
+//! The packet I/O backends
+
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod portable;
+#[cfg(target_os = "linux")]
+mod sys;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
+use crate::config::{Backend, Config};
+use crate::error::Error;
+use crate::relay::Relay;
+use std::io::{self, ErrorKind};
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The maximum size of a single packet
+const PACKET_SIZE_MAX: usize = 4096;
+/// The interval in which blocked workers wake up to check whether they should stop
+pub const WAKEUP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The liveness heartbeat of a worker
+///
+/// # Watchdog
+/// Every worker beats once per iteration of its forwarding loop, which wakes up at least every [`WAKEUP_INTERVAL`]. A
+/// worker that is wedged, e.g. on the relay lock, stops beating, so that the service manager watchdog can fire.
+#[derive(Debug)]
+pub struct Heartbeat {
+    /// The reference point for the beats
+    epoch: Instant,
+    /// The time of the last beat in milliseconds since `epoch`
+    last: AtomicU64,
+}
+impl Heartbeat {
+    /// Creates a new heartbeat that has just beaten
+    pub fn new() -> Self {
+        Self { epoch: Instant::now(), last: AtomicU64::new(0) }
+    }
+
+    /// Records a beat
+    pub fn beat(&self) {
+        let elapsed = u64::try_from(self.epoch.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// The time since the last beat
+    pub fn age(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.epoch.elapsed().saturating_sub(last)
+    }
+}
+
+/// Binds one socket per worker to the listening address
+pub fn bind(config: &Config) -> Result<Vec<UdpSocket>, Error> {
+    match config.WGPROXY_WORKERS {
+        1 => Ok(vec![UdpSocket::bind(config.WGPROXY_LISTEN)?]),
+        #[cfg(target_os = "linux")]
+        workers => {
+            // Bind the first socket and reuse its address, so that an ephemeral port is shared by all workers
+            let first = sys::bind_reuseport(&config.WGPROXY_LISTEN)?;
+            let address = first.local_addr()?;
+
+            // Bind the remaining sockets
+            let mut sockets = vec![first];
+            for _ in 1..workers {
+                let socket = sys::bind_reuseport(&address)?;
+                sockets.push(socket);
+            }
+            Ok(sockets)
+        }
+        #[cfg(not(target_os = "linux"))]
+        _ => Err(crate::error!("Multiple workers are only available on Linux")),
+    }
+}
+
+/// Runs the configured I/O backend until `stop` is set
+///
+/// # Concurrency
+/// All workers of a relay share its state, but only lock it to route a batch of received packets; receiving, sending
+/// and the packet copies run in parallel. Routing is a few hash map lookups per packet, which is cheap compared to the
+/// syscalls, so the lock is rarely contended. The session table is not sharded as the session limits, the eviction and
+/// the forwarding of server-initiated handshakes span all sessions regardless of the worker that received a packet.
+pub fn run(
+    socket: &UdpSocket,
+    config: &Config,
+    relay: &Mutex<Relay>,
+    (stop, heartbeat): (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    // Wake up periodically to check whether we should stop
+    socket.set_read_timeout(Some(WAKEUP_INTERVAL))?;
+    let control = (stop, heartbeat);
+    match config.WGPROXY_BACKEND {
+        Backend::Auto => auto(socket, config, relay, control),
+        Backend::Portable => portable::run(socket, relay, control),
+        #[cfg(target_os = "linux")]
+        Backend::Mmsg => mmsg::run(socket, config, relay, control),
+        #[cfg(not(target_os = "linux"))]
+        Backend::Mmsg => Err(crate::error!("The mmsg backend is only available on Linux")),
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        Backend::IoUring => auto(socket, config, relay, control),
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        Backend::IoUring => {
+            Err(crate::error!("The io-uring backend is only available on Linux with the `io-uring` feature"))
+        }
+    }
+}
+
+/// Runs the best backend available on the current platform
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables, reason = "The config is only used by Linux backends"))]
+fn auto(
+    socket: &UdpSocket,
+    config: &Config,
+    relay: &Mutex<Relay>,
+    control: (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if uring::is_supported() {
+        return uring::run(socket, config, relay, control);
+    }
+    #[cfg(target_os = "linux")]
+    if mmsg::is_supported(socket) {
+        return mmsg::run(socket, config, relay, control);
+    }
+
+    // Use the portable backend as last resort
+    portable::run(socket, relay, control)
+}
+
+/// Whether a receive error is just a periodic or signal-induced wakeup
+fn is_wakeup(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted)
+}
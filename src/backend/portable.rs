@@ -0,0 +1,43 @@
+//! The portable single-packet backend
+
+use crate::backend::{self, Heartbeat, PACKET_SIZE_MAX};
+use crate::error;
+use crate::error::Error;
+use crate::log;
+use crate::relay::Relay;
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Receives and forwards one packet per syscall
+pub fn run(
+    socket: &UdpSocket,
+    relay: &Mutex<Relay>,
+    (stop, heartbeat): (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    let mut buf = [0; PACKET_SIZE_MAX];
+    let mut destinations = Vec::new();
+    'network_loop: while !stop.load(Ordering::Relaxed) {
+        heartbeat.beat();
+
+        // Receive next inbound packet
+        let (buf_len, source_addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if backend::is_wakeup(&e) => continue 'network_loop,
+            Err(e) => return Err(error!(with: e, "Failed to receive inbound packet")),
+        };
+        let Some(packet) = buf.get(..buf_len) else {
+            // This should never happen as the received length is always within the buffer bounds
+            continue 'network_loop;
+        };
+
+        // Forward the packet
+        Relay::lock(relay)?.handle(packet, &source_addr, &mut destinations)?;
+        for destination in destinations.drain(..) {
+            // This is not necessarily fatal, but worth a warning
+            let result = socket.send_to(packet, destination);
+            let _ = log!(warn: result.map_err(|e| error!(with: e, "Failed to forward packet to {destination}")));
+        }
+    }
+    Ok(())
+}
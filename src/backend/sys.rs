@@ -0,0 +1,97 @@
+//! Helpers for the raw Linux socket APIs
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::{io, mem};
+
+/// The size of a `sockaddr_storage` as `socklen_t`
+pub const SOCKADDR_STORAGE_LEN: libc::socklen_t = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+/// Creates a zeroed `sockaddr_storage`
+pub fn sockaddr_storage() -> libc::sockaddr_storage {
+    // Safety: `sockaddr_storage` is a plain C struct where all-zero is a valid bit pattern
+    unsafe { mem::zeroed() }
+}
+
+/// Converts a `sockaddr_storage` to a socket address, or returns `None` if the address family is not supported
+pub fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match libc::c_int::from(storage.ss_family) {
+        libc::AF_INET => {
+            // Safety: The address family guarantees that the storage contains a `sockaddr_in`
+            let address = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(address.sin_port))))
+        }
+        libc::AF_INET6 => {
+            // Safety: The address family guarantees that the storage contains a `sockaddr_in6`
+            let address = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            let ip = Ipv6Addr::from(address.sin6_addr.s6_addr);
+            let port = u16::from_be(address.sin6_port);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, port, address.sin6_flowinfo, address.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
+
+/// Writes a socket address into a `sockaddr_storage` and returns the length of the written address
+pub fn from_socket_addr(address: &SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    match address {
+        SocketAddr::V4(address) => {
+            // Safety: `sockaddr_storage` is large enough and suitably aligned for any socket address type
+            let target = unsafe { &mut *(storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            target.sin_family = libc::AF_INET as libc::sa_family_t;
+            target.sin_port = address.port().to_be();
+            target.sin_addr = libc::in_addr { s_addr: u32::from(*address.ip()).to_be() };
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        }
+        SocketAddr::V6(address) => {
+            // Safety: `sockaddr_storage` is large enough and suitably aligned for any socket address type
+            let target = unsafe { &mut *(storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            target.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            target.sin6_port = address.port().to_be();
+            target.sin6_flowinfo = address.flowinfo();
+            target.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+            target.sin6_scope_id = address.scope_id();
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        }
+    }
+}
+
+/// Sets an integer socket option
+pub fn setsockopt(fd: RawFd, level: libc::c_int, option: libc::c_int, value: libc::c_int) -> Result<(), io::Error> {
+    let value_ptr = (&value as *const libc::c_int).cast();
+    let value_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // Safety: The option value is valid for the duration of the call
+    match unsafe { libc::setsockopt(fd, level, option, value_ptr, value_len) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Creates a new UDP socket with `SO_REUSEPORT` and binds it to the given address
+pub fn bind_reuseport(address: &SocketAddr) -> Result<UdpSocket, io::Error> {
+    let family = match address {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    // Create the socket
+    // Safety: This call has no memory safety preconditions
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: The file descriptor has just been created and is exclusively owned
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // Enable port sharing and bind the socket
+    setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    let mut storage = sockaddr_storage();
+    let storage_len = from_socket_addr(address, &mut storage);
+    let storage_ptr = (&storage as *const libc::sockaddr_storage).cast();
+    // Safety: The address is valid for the duration of the call
+    match unsafe { libc::bind(socket.as_raw_fd(), storage_ptr, storage_len) } {
+        0 => Ok(UdpSocket::from(socket)),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
@@ -0,0 +1,335 @@
+//! The Linux `io_uring` backend with registered buffers
+
+use crate::backend::sys::{self, SOCKADDR_STORAGE_LEN};
+use crate::backend::{self, Heartbeat, PACKET_SIZE_MAX};
+use crate::config::Config;
+use crate::error;
+use crate::error::Error;
+use crate::log;
+use crate::relay::Relay;
+use io_uring::register::Probe;
+use io_uring::types::{SubmitArgs, Timespec};
+use io_uring::{IoUring, cqueue, opcode, squeue, types};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{mem, ptr};
+
+/// The maximum amount of receive slots, as limited by the maximum amount of registered buffers
+const SLOTS_MAX: usize = 1 << 14;
+/// The user data flag to tag send completions
+const SEND: u64 = 1 << 63;
+/// The user data flag to tag cancel completions
+const CANCEL: u64 = 1 << 62;
+/// The maximum time to wait for the in-flight operations to complete on stop
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A receive slot with a registered buffer
+struct Slot {
+    /// The packet buffer
+    buffer: Vec<u8>,
+    /// The source address
+    name: libc::sockaddr_storage,
+    /// The I/O vector pointing into `buffer`
+    iovec: libc::iovec,
+    /// The message header pointing into `name` and `iovec`
+    header: libc::msghdr,
+    /// The destination addresses of the in-flight sends
+    destinations: Vec<(libc::sockaddr_storage, libc::socklen_t)>,
+    /// Whether a receive into the buffer is in flight
+    receiving: bool,
+    /// The amount of in-flight sends that still reference the buffer
+    pending: usize,
+}
+impl Slot {
+    /// Creates the given amount of receive slots
+    pub fn new_all(count: usize) -> Box<[Self]> {
+        let mut slots: Box<[Self]> = (0..count)
+            .map(|_| Self {
+                buffer: vec![0; PACKET_SIZE_MAX],
+                name: sys::sockaddr_storage(),
+                iovec: libc::iovec { iov_base: ptr::null_mut(), iov_len: 0 },
+                // Safety: `msghdr` is a plain C struct where all-zero is a valid bit pattern
+                header: unsafe { mem::zeroed() },
+                destinations: Vec::new(),
+                receiving: false,
+                pending: 0,
+            })
+            .collect();
+
+        // Setup the self-referential pointers; the boxed slice is never resized, so the pointers remain valid
+        for slot in slots.iter_mut() {
+            slot.iovec = libc::iovec { iov_base: slot.buffer.as_mut_ptr().cast(), iov_len: slot.buffer.len() };
+            slot.header.msg_name = ptr::addr_of_mut!(slot.name).cast();
+            slot.header.msg_iov = ptr::addr_of_mut!(slot.iovec);
+            slot.header.msg_iovlen = 1;
+        }
+        slots
+    }
+}
+
+/// Probes whether `io_uring` and the required operations are supported by the kernel
+pub fn is_supported() -> bool {
+    let probe = IoUring::new(2).and_then(|ring| {
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        Ok(probe)
+    });
+
+    // Check the required operations
+    match probe {
+        Ok(probe) if probe.is_supported(opcode::RecvMsg::CODE) && probe.is_supported(opcode::SendZc::CODE) => true,
+        Ok(_) => {
+            log!(info: error!("io_uring does not support the required operations, falling back to another backend"));
+            false
+        }
+        Err(e) => {
+            log!(info: error!(with: e, "io_uring is not available, falling back to another backend"));
+            false
+        }
+    }
+}
+
+/// Pushes an entry onto the submission queue, and submits the queue first if it is full
+///
+/// # Safety
+/// All buffers referenced by the entry must remain valid until the operation completes.
+unsafe fn push(ring: &mut IoUring, entry: &squeue::Entry) -> Result<(), Error> {
+    // Safety: The caller ensures that the buffers remain valid
+    while unsafe { ring.submission().push(entry) }.is_err() {
+        ring.submit().map_err(|e| error!(with: e, "Failed to submit io_uring entries"))?;
+    }
+    Ok(())
+}
+
+/// Queues a receive into the given slot
+fn recv(ring: &mut IoUring, fd: RawFd, index: usize, slot: &mut Slot) -> Result<(), Error> {
+    // Reset the address length as it is overwritten by the kernel
+    slot.header.msg_namelen = SOCKADDR_STORAGE_LEN;
+    let entry = opcode::RecvMsg::new(types::Fd(fd), &mut slot.header).build().user_data(index as u64);
+
+    // Safety: The slot is not reused before the receive completes
+    unsafe { push(ring, &entry)? };
+    slot.receiving = true;
+    Ok(())
+}
+
+/// Takes the available completions and tracks which operations are still in flight
+fn reap(ring: &mut IoUring, slots: &mut [Slot], completions: &mut Vec<(u64, i32, u32)>) {
+    for entry in ring.completion() {
+        let (user_data, flags) = (entry.user_data(), entry.flags());
+        let Some(slot) = slots.get_mut((user_data & !SEND) as usize).filter(|_| user_data & CANCEL == 0) else {
+            // Cancel requests do not reference a slot
+            continue;
+        };
+        match user_data & SEND {
+            0 => slot.receiving = false,
+            // A send is complete once the kernel has released the buffer
+            _ if !cqueue::more(flags) => slot.pending = slot.pending.saturating_sub(1),
+            _ => (),
+        }
+        completions.push((user_data, entry.result(), flags));
+    }
+}
+
+/// Routes a completed receive and stores the destination addresses in the slot
+fn route(slot: &mut Slot, result: i32, relay: &mut Relay, destinations: &mut Vec<SocketAddr>) -> Result<(), Error> {
+    // All previous sends have completed before the receive has been queued
+    slot.destinations.clear();
+    let Ok(len) = usize::try_from(result) else {
+        // The receive has failed
+        return Ok(());
+    };
+    let (Some(packet), Some(source_addr)) = (slot.buffer.get(..len), sys::to_socket_addr(&slot.name)) else {
+        // This should never happen for UDP sockets
+        return Ok(());
+    };
+
+    // Route the packet and keep the destination addresses alive until the sends complete
+    relay.handle(packet, &source_addr, destinations)?;
+    for destination in destinations.drain(..) {
+        let mut name = sys::sockaddr_storage();
+        let name_len = sys::from_socket_addr(&destination, &mut name);
+        slot.destinations.push((name, name_len));
+    }
+    Ok(())
+}
+
+/// Handles a completed and routed receive and queues the sends to all destinations
+fn on_recv(ring: &mut IoUring, fd: RawFd, (index, slot): (usize, &mut Slot), result: i32) -> Result<(), Error> {
+    let Ok(len) = usize::try_from(result) else {
+        // This is not necessarily fatal, but worth a warning
+        let e = io::Error::from_raw_os_error(result.saturating_neg());
+        log!(warn: error!(with: e, "Failed to receive inbound packet"));
+        return recv(ring, fd, index, slot);
+    };
+    let Some(packet) = slot.buffer.get(..len).filter(|_| !slot.destinations.is_empty()) else {
+        // The packet is dropped, so the slot can be reused immediately
+        return recv(ring, fd, index, slot);
+    };
+
+    // Send the packet directly from the registered buffer
+    for (name, name_len) in slot.destinations.iter() {
+        let entry = opcode::SendZc::new(types::Fd(fd), packet.as_ptr(), len as u32)
+            .buf_index(Some(index as u16))
+            .dest_addr((name as *const libc::sockaddr_storage).cast())
+            .dest_addr_len(*name_len)
+            .build()
+            .user_data(index as u64 | SEND);
+
+        // Safety: The slot is not reused before all pending sends have completed
+        unsafe { push(ring, &entry)? };
+        slot.pending = slot.pending.saturating_add(1);
+    }
+    Ok(())
+}
+
+/// Handles a completed send and requeues the receive once the slot is no longer in use
+fn on_send(
+    ring: &mut IoUring,
+    fd: RawFd,
+    (index, slot): (usize, &mut Slot),
+    result: i32,
+    flags: u32,
+) -> Result<(), Error> {
+    if result < 0 && !cqueue::notif(flags) {
+        // This is not necessarily fatal, but worth a warning
+        let e = io::Error::from_raw_os_error(result.saturating_neg());
+        log!(warn: error!(with: e, "Failed to forward packet"));
+    }
+    if cqueue::more(flags) {
+        // A notification follows once the kernel has released the buffer
+        return Ok(());
+    }
+
+    // Requeue the receive once all sends have released the buffer
+    match (slot.pending, slot.receiving) {
+        (0, false) => recv(ring, fd, index, slot),
+        _ => Ok(()),
+    }
+}
+
+/// Cancels the in-flight receives and waits until all operations have completed, so that the slots can be freed
+///
+/// # Note
+/// Closing the ring cancels the in-flight operations asynchronously, so the kernel could still write into the slots
+/// after they have been freed. Returns `false` if the operations did not complete in time.
+fn cancel(ring: &mut IoUring, slots: &mut [Slot]) -> bool {
+    // Cancel the receives; sends complete on their own
+    for (index, _) in slots.iter().enumerate().filter(|(_, slot)| slot.receiving) {
+        let entry = opcode::AsyncCancel::new(index as u64).build().user_data(CANCEL);
+        // Safety: A cancel request does not reference any buffers
+        if log!(warn: unsafe { push(ring, &entry) }).is_err() {
+            return false;
+        }
+    }
+
+    // Wait for the remaining completions
+    let mut completions = Vec::new();
+    let timeout = Timespec::from(backend::WAKEUP_INTERVAL);
+    let args = SubmitArgs::new().timespec(&timeout);
+    let started = Instant::now();
+    while slots.iter().any(|slot| slot.receiving || slot.pending > 0) {
+        if started.elapsed() >= CANCEL_TIMEOUT {
+            return false;
+        }
+        match ring.submitter().submit_with_args(1, &args) {
+            Ok(_) => (),
+            Err(e) if e.raw_os_error() == Some(libc::ETIME) || backend::is_wakeup(&e) => (),
+            Err(e) => {
+                log!(warn: error!(with: e, "Failed to wait for io_uring completions"));
+                return false;
+            }
+        }
+        reap(ring, slots, &mut completions);
+        completions.clear();
+    }
+    true
+}
+
+/// Receives and forwards packets via `io_uring`
+pub fn run(
+    socket: &UdpSocket,
+    config: &Config,
+    relay: &Mutex<Relay>,
+    control: (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    let fd = socket.as_raw_fd();
+    let slot_count = config.WGPROXY_BATCH_SIZE.min(SLOTS_MAX);
+
+    // Setup the slots before the ring, so that the ring is dropped first
+    let mut slots = Slot::new_all(slot_count);
+    let entries = slot_count.saturating_mul(2).next_power_of_two() as u32;
+    let mut ring = IoUring::new(entries).map_err(|e| error!(with: e, "Failed to setup io_uring"))?;
+
+    // Register the buffers
+    let iovecs: Vec<_> = slots.iter().map(|slot| slot.iovec).collect();
+    // Safety: The buffers outlive the ring
+    unsafe { ring.submitter().register_buffers(&iovecs) }
+        .map_err(|e| error!(with: e, "Failed to register io_uring buffers"))?;
+
+    // Forward the packets, and wait for the in-flight operations before the slots are freed
+    let result = forward(&mut ring, fd, &mut slots, relay, control);
+    if !cancel(&mut ring, &mut slots) {
+        // Leak the slots instead of risking the kernel writing into freed memory
+        log!(warn: error!("Failed to cancel the in-flight io_uring operations, leaking their buffers"));
+        Box::leak(slots);
+    }
+    result
+}
+
+/// Queues the initial receives and processes the completions until `stop` is set
+fn forward(
+    ring: &mut IoUring,
+    fd: RawFd,
+    slots: &mut [Slot],
+    relay: &Mutex<Relay>,
+    (stop, heartbeat): (&AtomicBool, &Heartbeat),
+) -> Result<(), Error> {
+    // Queue the initial receives
+    for (index, slot) in slots.iter_mut().enumerate() {
+        recv(ring, fd, index, slot)?;
+    }
+
+    // Process the completions
+    let mut completions = Vec::new();
+    let mut destinations = Vec::new();
+    let timeout = Timespec::from(backend::WAKEUP_INTERVAL);
+    let args = SubmitArgs::new().timespec(&timeout);
+    'network_loop: while !stop.load(Ordering::Relaxed) {
+        heartbeat.beat();
+        match ring.submitter().submit_with_args(1, &args) {
+            Ok(_) => (),
+            Err(e) if e.raw_os_error() == Some(libc::ETIME) || backend::is_wakeup(&e) => continue 'network_loop,
+            Err(e) => return Err(error!(with: e, "Failed to wait for io_uring completions")),
+        }
+        reap(ring, slots, &mut completions);
+
+        // Route the received packets; the relay is only locked for routing, so that the I/O of all workers runs in
+        // parallel
+        let mut relay = Relay::lock(relay)?;
+        for (user_data, result, _) in completions.iter().filter(|(user_data, ..)| user_data & SEND == 0) {
+            if let Some(slot) = slots.get_mut(*user_data as usize) {
+                route(slot, *result, &mut relay, &mut destinations)?;
+            }
+        }
+        drop(relay);
+
+        // Handle the completions
+        for (user_data, result, flags) in completions.drain(..) {
+            let index = (user_data & !SEND) as usize;
+            let Some(slot) = slots.get_mut(index) else {
+                // This should never happen as we only submit entries for valid slots
+                continue;
+            };
+            match user_data & SEND {
+                0 => on_recv(ring, fd, (index, slot), result)?,
+                _ => on_send(ring, fd, (index, slot), result, flags)?,
+            }
+        }
+    }
+    Ok(())
+}
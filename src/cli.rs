@@ -0,0 +1,313 @@
+//! The command-line interface
+
+use crate::config::Config;
+use crate::error;
+use crate::error::Error;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// A command-line flag that mirrors an environment variable
+struct Flag {
+    /// The name of the mirrored environment variable
+    name: &'static str,
+    /// The default value if any
+    default: Option<&'static str>,
+    /// The description
+    help: &'static str,
+}
+impl Flag {
+    /// The flag name without the leading `--`, e.g. `timeout-uplink` for `WGPROXY_TIMEOUT_UPLINK`
+    fn flag(&self) -> String {
+        let name = self.name.strip_prefix("WGPROXY_").unwrap_or(self.name);
+        name.to_lowercase().replace('_', "-")
+    }
+}
+
+/// All flags in the order of [`Config`]
+const FLAGS: &[Flag] = &[
+    Flag {
+        name: "WGPROXY_SERVER",
+        default: None,
+        help: "The server address to forward the traffic to (required unless --wg-config is set)",
+    },
+    Flag {
+        name: "WGPROXY_PUBKEY",
+        default: None,
+        help: "The comma-separated base64 server public keys[@<not-before>..<not-after>] (required unless --wg-config)",
+    },
+    Flag {
+        name: "WGPROXY_WG_CONFIG",
+        default: None,
+        help: "The wg-quick client config to take the server address and public key from if they are not set",
+    },
+    Flag {
+        name: "WGPROXY_LISTEN",
+        default: Some(Config::WGPROXY_LISTEN_DEFAULT),
+        help: "The address to listen on and to use for relaying",
+    },
+    Flag {
+        name: "WGPROXY_ALLOW",
+        default: None,
+        help: "The comma-separated client address prefixes that may start a session; defaults to all",
+    },
+    Flag {
+        name: "WGPROXY_DENY",
+        default: None,
+        help: "The comma-separated client address prefixes that may not start a session",
+    },
+    Flag {
+        name: "WGPROXY_TIMEOUT",
+        default: Some(Config::WGPROXY_TIMEOUT_DEFAULT),
+        help: "The timeout for NAT mappings to expire, e.g. `90s`, `5m` or `1h30m`",
+    },
+    Flag {
+        name: "WGPROXY_TIMEOUT_UPLINK",
+        default: None,
+        help: "The timeout for the uplink direction; defaults to --timeout",
+    },
+    Flag {
+        name: "WGPROXY_TIMEOUT_DOWNLINK",
+        default: None,
+        help: "The timeout for the downlink direction; defaults to --timeout",
+    },
+    Flag {
+        name: "WGPROXY_IDLE_POLICY",
+        default: Some(Config::WGPROXY_IDLE_POLICY_DEFAULT),
+        help: "The policy how to detect idle sessions: `min`, `max`, `uplink` or `downlink`",
+    },
+    Flag {
+        name: "WGPROXY_REKEY_GRACE",
+        default: None,
+        help: "The grace period after `REJECT_AFTER_TIME` before a session without re-key expires",
+    },
+    Flag {
+        name: "WGPROXY_LOGLEVEL",
+        default: Some(Config::WGPROXY_LOGLEVEL_DEFAULT),
+        help: "The log level: `error`, `warn`, `info`, `debug` or `trace`, or `0` (errors only) to `3` (debug)",
+    },
+    Flag {
+        name: "WGPROXY_AMPLIFICATION_LIMIT",
+        default: Some(Config::WGPROXY_AMPLIFICATION_LIMIT_DEFAULT),
+        help: "The maximum amount of packets to send to an unreachable client, or `0` to disable the guard",
+    },
+    Flag {
+        name: "WGPROXY_AMPLIFICATION_RATIO",
+        default: Some(Config::WGPROXY_AMPLIFICATION_RATIO_DEFAULT),
+        help: "The maximum ratio of bytes sent to an unreachable client per byte received from it",
+    },
+    Flag {
+        name: "WGPROXY_REPLAY_WINDOW",
+        default: Some(Config::WGPROXY_REPLAY_WINDOW_DEFAULT),
+        help: "The minimum duration to remember handshake MAC1s",
+    },
+    Flag {
+        name: "WGPROXY_REPLAY_CAPACITY",
+        default: Some(Config::WGPROXY_REPLAY_CAPACITY_DEFAULT),
+        help: "The maximum amount of remembered handshake MAC1s",
+    },
+    Flag {
+        name: "WGPROXY_TAKEOVER",
+        default: Some(Config::WGPROXY_TAKEOVER_DEFAULT),
+        help: "The session takeover policy: `never`, `idle:<duration>` or `always`",
+    },
+    Flag {
+        name: "WGPROXY_MAX_SESSIONS",
+        default: Some(Config::WGPROXY_MAX_SESSIONS_DEFAULT),
+        help: "The maximum amount of concurrent sessions",
+    },
+    Flag {
+        name: "WGPROXY_EVICTION",
+        default: Some(Config::WGPROXY_EVICTION_DEFAULT),
+        help: "The eviction policy if the session limit has been reached: `reject`, `lru` or `pending`",
+    },
+    Flag {
+        name: "WGPROXY_MAX_SESSIONS_PER_PREFIX",
+        default: Some(Config::WGPROXY_MAX_SESSIONS_PER_PREFIX_DEFAULT),
+        help: "The maximum amount of concurrent sessions per client address prefix, or `0` to disable the limit",
+    },
+    Flag {
+        name: "WGPROXY_PREFIX_V4",
+        default: Some(Config::WGPROXY_PREFIX_V4_DEFAULT),
+        help: "The IPv4 prefix length to group client addresses",
+    },
+    Flag {
+        name: "WGPROXY_PREFIX_V6",
+        default: Some(Config::WGPROXY_PREFIX_V6_DEFAULT),
+        help: "The IPv6 prefix length to group client addresses",
+    },
+    Flag {
+        name: "WGPROXY_BACKEND",
+        default: Some(Config::WGPROXY_BACKEND_DEFAULT),
+        help: "The packet I/O backend: `auto`, `portable`, `mmsg` or `io-uring`",
+    },
+    Flag {
+        name: "WGPROXY_BATCH_SIZE",
+        default: Some(Config::WGPROXY_BATCH_SIZE_DEFAULT),
+        help: "The maximum amount of packets per syscall for batching backends",
+    },
+    Flag {
+        name: "WGPROXY_OFFLOAD",
+        default: Some(Config::WGPROXY_OFFLOAD_DEFAULT),
+        help: "Whether to use UDP GRO/GSO offloading with the `mmsg` backend",
+    },
+    Flag {
+        name: "WGPROXY_WORKERS",
+        default: Some(Config::WGPROXY_WORKERS_DEFAULT),
+        help: "The amount of worker threads",
+    },
+    Flag {
+        name: "WGPROXY_STATE",
+        default: None,
+        help: "The path to the state file to persist the replay cache and the sessions",
+    },
+    Flag {
+        name: "WGPROXY_STATE_INTERVAL",
+        default: Some(Config::WGPROXY_STATE_INTERVAL_DEFAULT),
+        help: "The interval between two state snapshots",
+    },
+    Flag {
+        name: "WGPROXY_DRAIN",
+        default: Some(Config::WGPROXY_DRAIN_DEFAULT),
+        help: "The drain period after a termination signal",
+    },
+    Flag { name: "WGPROXY_HANDOVER", default: None, help: "The path of the Unix socket for zero-downtime upgrades" },
+    Flag { name: "WGPROXY_USER", default: None, help: "The user to switch to after the sockets have been bound" },
+    Flag { name: "WGPROXY_GROUP", default: None, help: "The group to switch to after the sockets have been bound" },
+    Flag {
+        name: "WGPROXY_SANDBOX",
+        default: Some(Config::WGPROXY_SANDBOX_DEFAULT),
+        help: "The seccomp and Landlock sandbox: `true` (kill on other syscalls), `errno` (fail them) or `false`",
+    },
+    Flag {
+        name: "WGPROXY_CONFIG",
+        default: None,
+        help: "The path to a TOML config file with multiple relays; the other options serve as fallback",
+    },
+];
+
+/// Whether the given name is a known environment variable
+pub(crate) fn is_setting(name: &str) -> bool {
+    FLAGS.iter().any(|flag| flag.name == name)
+}
+
+/// The command to execute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run the relay
+    Run,
+    /// Validate the config, print it and report all problems
+    Check,
+    /// Print the given wg-quick config with its `Endpoint` pointed at the relay
+    RewriteEndpoint {
+        /// The path to the wg-quick config
+        config: PathBuf,
+        /// The public address of the relay
+        endpoint: String,
+    },
+    /// Print the help
+    Help,
+    /// Print the version
+    Version,
+}
+
+/// The parsed command-line arguments
+#[derive(Debug, Clone)]
+pub struct Args {
+    /// The command to execute
+    pub command: Command,
+    /// The environment variables that are overridden by flags
+    pub overrides: Vec<(&'static str, String)>,
+}
+impl Args {
+    /// Parses the command-line arguments without the program name
+    ///
+    /// # Note
+    /// Flags can be specified as `--flag value` or `--flag=value`; the values are validated when the config is loaded.
+    pub fn parse<I>(args: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter();
+        let (mut overrides, mut positionals) = (Vec::new(), Vec::new());
+        while let Some(arg) = args.next() {
+            // Handle the informational flags
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self { command: Command::Help, overrides }),
+                "-V" | "--version" => return Ok(Self { command: Command::Version, overrides }),
+                _ => (),
+            }
+
+            // Split the flag and its value
+            let Some(flag) = arg.strip_prefix("--") else {
+                positionals.push(arg);
+                continue;
+            };
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+
+            // Map the flag to its environment variable
+            let Some(option) = FLAGS.iter().find(|option| option.flag() == flag) else {
+                return Err(error!(r#"Unknown flag "--{flag}"; see --help"#));
+            };
+            let Some(value) = value.or_else(|| args.next()) else {
+                return Err(error!(r#"Missing value for flag "--{flag}""#));
+            };
+            overrides.push((option.name, value));
+        }
+
+        // Parse the subcommand
+        let command = match positionals.as_slice() {
+            [] => Command::Run,
+            [command] if command == "check" => Command::Check,
+            [command, config, endpoint] if command == "rewrite-endpoint" => {
+                Command::RewriteEndpoint { config: PathBuf::from(config), endpoint: endpoint.clone() }
+            }
+            [command, ..] if command == "rewrite-endpoint" => {
+                return Err(error!("Usage: wgproxy rewrite-endpoint <WG_CONFIG> <ENDPOINT>; see --help"));
+            }
+            [argument, ..] => return Err(error!(r#"Unexpected argument "{argument}"; see --help"#)),
+        };
+        Ok(Self { command, overrides })
+    }
+
+    /// Applies the overrides so that they take precedence over the environment for all config loads
+    pub fn apply(&self) -> Result<(), Error> {
+        for (name, value) in &self.overrides {
+            Config::set_override(name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The version string
+pub fn version() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// The help text
+pub fn help() -> String {
+    let mut help = format!("{}\n{}\n\n", version(), env!("CARGO_PKG_DESCRIPTION"));
+    help.push_str("Usage: wgproxy [OPTIONS] [COMMAND]\n\n");
+    help.push_str("Commands:\n");
+    help.push_str("  check\n      Validates the config, prints it and reports all problems\n");
+    help.push_str("  rewrite-endpoint <WG_CONFIG> <ENDPOINT>\n");
+    help.push_str("      Prints the wg-quick client config with its Endpoint pointed at the relay\n\n");
+    help.push_str("Every option can also be set via its environment variable; options take precedence.\n");
+    help.push_str("The value of an environment variable can also be read from the file given in <VARIABLE>_FILE.\n");
+    help.push_str("The config file supports a TOML subset: `key = value` lines in the global table and `[[relay]]`\n");
+    help.push_str("tables, with single-line strings, integers, booleans and single-line arrays of them.\n\n");
+    help.push_str("Options:\n");
+    for option in FLAGS {
+        // Writing to a string cannot fail
+        let _ = writeln!(help, "  --{} <VALUE>", option.flag());
+        let _ = match option.default {
+            Some(default) => writeln!(help, "      {} [env: {}] [default: {default}]", option.help, option.name),
+            None => writeln!(help, "      {} [env: {}]", option.help, option.name),
+        };
+    }
+    help.push_str("  -h, --help\n      Prints this help\n");
+    help.push_str("  -V, --version\n      Prints the version\n");
+    help
+}
@@ -2,12 +2,291 @@
 
 use crate::error;
 use crate::error::Error;
+use crate::wgquick::Peer;
 use base64ct::{Base64, Encoding};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::env::{self, VarError};
 use std::fmt::{self, Display, Formatter};
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::fs;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Raw config values keyed by their environment variable names
+type Values = BTreeMap<String, String>;
+
+/// Process-global overrides for the environment variables, e.g. from command-line flags
+static OVERRIDES: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// The policy whether a new client handshake may take over an existing session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TakeoverPolicy {
+    /// An existing session is never taken over
+    Never,
+    /// An existing session may be taken over if the client has been idle for longer than the given duration
+    Idle(Duration),
+    /// An existing session may always be taken over by a valid handshake with a fresh MAC1
+    Always,
+}
+
+/// The policy how to detect idle sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdlePolicy {
+    /// A session is idle if either direction has timed out
+    Min,
+    /// A session is idle if both directions have timed out
+    Max,
+    /// A session is idle if the uplink direction has timed out
+    Uplink,
+    /// A session is idle if the downlink direction has timed out
+    Downlink,
+}
+
+/// The policy which session to evict if the session limit has been reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// New sessions are rejected, unless the least recently active session may be taken over
+    Reject,
+    /// The least recently active session is evicted
+    LeastRecentlyActive,
+    /// The least recently active pending session is evicted, otherwise new sessions are rejected
+    Pending,
+}
+
+/// The packet I/O backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The best backend available on the current platform
+    Auto,
+    /// The portable single-packet backend
+    Portable,
+    /// The Linux `recvmmsg`/`sendmmsg` batching backend
+    Mmsg,
+    /// The Linux `io_uring` backend with registered buffers
+    IoUring,
+}
+
+/// The sandbox mode of the relay process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    /// The relay is not sandboxed
+    Off,
+    /// Syscalls that are not on the allowlist kill the process
+    Kill,
+    /// Syscalls that are not on the allowlist fail with `EPERM` and are logged by the kernel
+    Errno,
+}
+
+/// An IP address prefix for client access control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    /// The network address
+    pub address: IpAddr,
+    /// The prefix length
+    pub length: u8,
+}
+impl Prefix {
+    /// Whether the prefix contains the given address; IPv4-mapped IPv6 addresses are matched as IPv4 addresses
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address.to_canonical(), address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let shift = 32u32.saturating_sub(u32::from(self.length));
+                let mask = u32::MAX.checked_shl(shift).unwrap_or_default();
+                network.to_bits() & mask == address.to_bits() & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let shift = 128u32.saturating_sub(u32::from(self.length));
+                let mask = u128::MAX.checked_shl(shift).unwrap_or_default();
+                network.to_bits() & mask == address.to_bits() & mask
+            }
+            _ => false,
+        }
+    }
+}
+impl FromStr for Prefix {
+    type Err = Error;
+
+    fn from_str(prefix: &str) -> Result<Self, Self::Err> {
+        // A plain address is a prefix with the full length
+        let (address, length) = prefix.split_once('/').unwrap_or((prefix, ""));
+        let maybe_address: Result<IpAddr, _> = address.trim().parse();
+        let address = maybe_address.map_err(|e| error!(with: e, r#"Invalid prefix address "{prefix}""#))?;
+        let length_max = match address.to_canonical() {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let length = match length.trim() {
+            "" => length_max,
+            length => {
+                let maybe_length: Result<u8, _> = length.parse();
+                maybe_length.map_err(|e| error!(with: e, r#"Invalid prefix length "{prefix}""#))?
+            }
+        };
+        match length {
+            length if length <= length_max => Ok(Self { address, length }),
+            _ => Err(error!(r#"Invalid prefix length "{prefix}""#)),
+        }
+    }
+}
+impl Display for Prefix {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)
+    }
+}
+
+/// A server public key with an optional validity period, e.g. for key rotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey {
+    /// The public key
+    pub key: [u8; 32],
+    /// The time before which the key is not valid yet, if any
+    pub not_before: Option<SystemTime>,
+    /// The time after which the key is not valid anymore, if any
+    pub not_after: Option<SystemTime>,
+}
+impl PublicKey {
+    /// Creates a public key that is valid at any time
+    pub const fn new(key: [u8; 32]) -> Self {
+        Self { key, not_before: None, not_after: None }
+    }
+
+    /// Whether the key is valid at the given time
+    pub fn is_valid_at(&self, time: SystemTime) -> bool {
+        let is_started = self.not_before.is_none_or(|not_before| time >= not_before);
+        is_started && !self.is_expired_at(time)
+    }
+
+    /// Whether the key has expired at the given time, so that it will never be valid again
+    pub fn is_expired_at(&self, time: SystemTime) -> bool {
+        self.not_after.is_some_and(|not_after| time > not_after)
+    }
+
+    /// Parses a timestamp like `2026-11-01T00:00:00Z`, a date like `2026-11-01` or Unix seconds like `1793491200`
+    fn timestamp(timestamp: &str) -> Result<SystemTime, Error> {
+        let invalid = || {
+            let expected = r#""2026-11-01T00:00:00Z", "2026-11-01" or Unix seconds like "1793491200""#;
+            error!(r#"Invalid timestamp "{timestamp}"; expected e.g. {expected}"#)
+        };
+        if let Ok(seconds) = timestamp.parse() {
+            // Plain integers are Unix timestamps
+            return UNIX_EPOCH.checked_add(Duration::from_secs(seconds)).ok_or_else(invalid);
+        }
+
+        // Split the date and the optional UTC time
+        let (date, time) = match timestamp.split_once('T') {
+            Some((date, time)) => (date, time.strip_suffix('Z').ok_or_else(invalid)?),
+            None => (timestamp, "00:00:00"),
+        };
+        let field = |field: Option<&str>, digits: usize| {
+            let field = field.filter(|field| field.len() == digits).ok_or_else(invalid)?;
+            field.parse::<u64>().map_err(|_| invalid())
+        };
+        let mut date = date.split('-');
+        let (year, month, day) = (field(date.next(), 4)?, field(date.next(), 2)?, field(date.next(), 2)?);
+        let mut time = time.split(':');
+        let (hour, minute, second) = (field(time.next(), 2)?, field(time.next(), 2)?, field(time.next(), 2)?);
+        let (None, None) = (date.next(), time.next()) else {
+            return Err(invalid());
+        };
+
+        // Validate the fields and count the days since the Unix epoch
+        let month_days = Self::month_days(year);
+        let Some(days_of_month) = month_days.get((month as usize).wrapping_sub(1)) else {
+            return Err(invalid());
+        };
+        if year < 1970 || day == 0 || day > *days_of_month || hour > 23 || minute > 59 || second > 59 {
+            return Err(invalid());
+        }
+        let days = [
+            (1970..year).map(|year| Self::month_days(year).iter().sum::<u64>()).sum::<u64>(),
+            month_days.iter().take((month as usize).saturating_sub(1)).sum::<u64>(),
+            day.saturating_sub(1),
+        ];
+        let seconds = [
+            days.iter().sum::<u64>().saturating_mul(86400),
+            hour.saturating_mul(3600),
+            minute.saturating_mul(60),
+            second,
+        ];
+        let seconds = seconds.into_iter().fold(0, u64::saturating_add);
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds)).ok_or_else(invalid)
+    }
+
+    /// Formats a timestamp as `2026-11-01T00:00:00Z`
+    fn format_timestamp(timestamp: SystemTime) -> String {
+        let seconds = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (mut days, time) = (seconds / 86400, seconds % 86400);
+
+        // Count the years and months
+        let mut year = 1970;
+        while days >= Self::month_days(year).iter().sum::<u64>() {
+            days = days.saturating_sub(Self::month_days(year).iter().sum::<u64>());
+            year = year.saturating_add(1);
+        }
+        let mut month = 1u64;
+        for month_days in Self::month_days(year) {
+            if days < month_days {
+                break;
+            }
+            days = days.saturating_sub(month_days);
+            month = month.saturating_add(1);
+        }
+        let day = days.saturating_add(1);
+        format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", time / 3600, time % 3600 / 60, time % 60)
+    }
+
+    /// The amount of days per month in the given year
+    fn month_days(year: u64) -> [u64; 12] {
+        let is_leap = (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+        let february = if is_leap { 29 } else { 28 };
+        [31, february, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
+    }
+}
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(pubkey: &str) -> Result<Self, Self::Err> {
+        // Split the key and the optional validity period
+        let (key, validity) = pubkey.split_once('@').unwrap_or((pubkey, ".."));
+        let Some((not_before, not_after)) = validity.split_once("..") else {
+            return Err(error!(r#"Invalid validity period "{validity}"; expected "<not-before>..<not-after>""#));
+        };
+
+        // Decode the key, and ensure that the decoded public key is exactly 32 bytes
+        let key = key.trim();
+        let binary =
+            Base64::decode_vec(key).map_err(|e| error!(with: e, r#"Failed to base64-decode public key "{key}""#))?;
+        let key = <[u8; 32]>::try_from(binary).map_err(|_| error!(r#"Invalid public key "{key}""#))?;
+
+        // Parse the validity period where either end may be open
+        let timestamp = |timestamp: &str| match timestamp.trim() {
+            "" => Ok(None),
+            timestamp => Self::timestamp(timestamp).map(Some),
+        };
+        let (not_before, not_after) = (timestamp(not_before)?, timestamp(not_after)?);
+        match (not_before, not_after) {
+            (Some(not_before), Some(not_after)) if not_before > not_after => {
+                Err(error!(r#"Invalid validity period "{validity}"; the key expires before it becomes valid"#))
+            }
+            _ => Ok(Self { key, not_before, not_after }),
+        }
+    }
+}
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", Base64::encode_string(&self.key))?;
+        if self.not_before.is_some() || self.not_after.is_some() {
+            // Append the validity period
+            let not_before = self.not_before.map(Self::format_timestamp).unwrap_or_default();
+            let not_after = self.not_after.map(Self::format_timestamp).unwrap_or_default();
+            write!(f, "@{not_before}..{not_after}")?;
+        }
+        Ok(())
+    }
+}
 
 /// The server config
 #[derive(Debug, Clone)]
@@ -15,124 +294,936 @@ use std::time::Duration;
 pub struct Config {
     /// The server address to forward the traffic to
     ///
+    /// # Note
+    /// DNS names are re-resolved every 30 seconds and on reload; new sessions use the latest address, while existing
+    /// sessions keep theirs.
+    ///
     /// # Example
     /// An `address:port` combination
     pub WGPROXY_SERVER: String,
-    /// The server public key for handshake validation
+    /// The server public keys for handshake validation
     ///
     /// # Note
-    /// The public key is used for handshake verfication and quick rejection when a new proxy connection is created.
+    /// The public keys are used for handshake verfication and quick rejection when a new proxy connection is created.
     /// This is a security feature to ensure that the relay will not forward arbitrary rogue packets.
-    /// **If the handshake does not match the configured public key, the packet will be dropped.**
-    pub WGPROXY_PUBKEY: [u8; 32],
+    /// **If the handshake does not match any of the configured public keys, the packet will be dropped.**
+    ///
+    /// # Key Rotation
+    /// Every key can be restricted to a validity period by appending `@<not-before>..<not-after>`, where either end may
+    /// be omitted and the timestamps are given like `2026-11-01T00:00:00Z`, `2026-11-01` or as Unix seconds. Keys are
+    /// only accepted within their validity period, and dropped once they have expired. This allows to configure the old
+    /// and the new key with an overlapping rotation window, e.g.
+    /// `<old key>@..2026-11-08,<new key>@2026-11-01..`.
+    ///
+    /// # Example
+    /// A comma-separated list of base64-encoded public keys with optional validity periods
+    pub WGPROXY_PUBKEY: Vec<PublicKey>,
+    /// The wg-quick client config to derive the server address and public key from
+    ///
+    /// # Note
+    /// The `Endpoint` and the `PublicKey` of the single `[Peer]` section are used if [`Self::WGPROXY_SERVER`] or
+    /// [`Self::WGPROXY_PUBKEY`] are not specified. The config is re-read on reload.
+    ///
+    /// # Example
+    /// A path like `/etc/wireguard/wg0.conf`, or unset
+    pub WGPROXY_WG_CONFIG: Option<PathBuf>,
     /// The address to listen on and to use for relaying
     ///
     /// # Example
     /// An inclusive range of ports, defaults to [`Self::WGPROXY_LISTEN_DEFAULT`]
     pub WGPROXY_LISTEN: SocketAddr,
+    /// The client address prefixes that may start a new session
+    ///
+    /// # Note
+    /// If the list is not empty, a handshake from a client outside of all listed prefixes is dropped. Existing sessions
+    /// are not affected by a config reload.
+    ///
+    /// # Example
+    /// A comma-separated list of prefixes like `192.0.2.0/24` or addresses, or unset to allow all clients
+    pub WGPROXY_ALLOW: Vec<Prefix>,
+    /// The client address prefixes that may not start a new session
+    ///
+    /// # Note
+    /// This takes precedence over [`Self::WGPROXY_ALLOW`].
+    ///
+    /// # Example
+    /// A comma-separated list of prefixes like `2001:db8::/32` or addresses, or unset to deny no clients
+    pub WGPROXY_DENY: Vec<Prefix>,
     /// The timeout duration for NAT mappings to expire
     ///
     /// # Example
-    /// A duration in seconds, defaults to [`Self::WGPROXY_TIMEOUT_DEFAULT`]
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to
+    /// [`Self::WGPROXY_TIMEOUT_DEFAULT`]
     pub WGPROXY_TIMEOUT: Duration,
+    /// The timeout duration for the uplink direction (client to server)
+    ///
+    /// # Example
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to [`Self::WGPROXY_TIMEOUT`]
+    pub WGPROXY_TIMEOUT_UPLINK: Duration,
+    /// The timeout duration for the downlink direction (server to client)
+    ///
+    /// # Example
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to [`Self::WGPROXY_TIMEOUT`]
+    pub WGPROXY_TIMEOUT_DOWNLINK: Duration,
+    /// The policy how to detect idle sessions
+    ///
+    /// # Possible Values
+    /// Possible values are:
+    /// - `min`: A session expires if either the uplink or the downlink direction has timed out
+    /// - `max`: A session expires if both the uplink and the downlink direction have timed out
+    /// - `uplink`: A session expires if the uplink direction has timed out
+    /// - `downlink`: A session expires if the downlink direction has timed out
+    ///
+    /// # Example
+    /// A policy value, defaults to [`Self::WGPROXY_IDLE_POLICY_DEFAULT`]
+    pub WGPROXY_IDLE_POLICY: IdlePolicy,
+    /// The grace period after WireGuard's `REJECT_AFTER_TIME` before a session without a completed re-key expires
+    ///
+    /// # Note
+    /// WireGuard peers re-key their session every two minutes and reject keys older than 180 seconds, so a session
+    /// without a completed handshake within that time cannot carry any valid traffic anymore. If set, such sessions
+    /// expire independent of their raw traffic.
+    ///
+    /// # Example
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, disabled if unspecified
+    pub WGPROXY_REKEY_GRACE: Option<Duration>,
     /// The log level
     ///
     /// # Possible Values
     /// Possible values are:
-    /// - `0`: Logs **errors** only
-    /// - `1`: Logs **warnings** and **errors**
-    /// - `2`: Logs **informational** messages, **warnings**, and **errors**
-    /// - `3`: Logs **debug** and **informational** messages, **warnings**, and **errors**
+    /// - `0` or `error`: Logs **errors** only
+    /// - `1` or `warn`: Logs **warnings** and **errors**
+    /// - `2` or `info`: Logs **informational** messages, **warnings**, and **errors**
+    /// - `3`, `debug` or `trace`: Logs **debug** and **informational** messages, **warnings**, and **errors**
     ///
     /// # Example
-    /// A positive integer value, defaults to [`Self::WGPROXY_LOGLEVEL_DEFAULT`]
+    /// A log level name or integer value, defaults to [`Self::WGPROXY_LOGLEVEL_DEFAULT`]
     pub WGPROXY_LOGLEVEL: u8,
+    /// The maximum amount of packets to send to a client before it has demonstrated reachability
+    ///
+    /// # Note
+    /// A client demonstrates reachability by sending a transport data packet that is addressed to the sender index of
+    /// the handshake response forwarded to it. Until then, the relay only forwards up to this amount of packets, and only as long as the
+    /// forwarded bytes stay within [`Self::WGPROXY_AMPLIFICATION_RATIO`] times the bytes received from the client.
+    /// This is a security feature to ensure that spoofed handshakes cannot turn the relay into a traffic amplifier.
+    /// **Excess packets are dropped and counted per session.**
+    ///
+    /// # Example
+    /// A positive integer value or `0` to disable the guard, defaults to [`Self::WGPROXY_AMPLIFICATION_LIMIT_DEFAULT`]
+    pub WGPROXY_AMPLIFICATION_LIMIT: u64,
+    /// The maximum ratio of bytes sent to a client per byte received from that client before it has demonstrated
+    /// reachability
+    ///
+    /// # Example
+    /// A positive integer value, defaults to [`Self::WGPROXY_AMPLIFICATION_RATIO_DEFAULT`]
+    pub WGPROXY_AMPLIFICATION_RATIO: u64,
+    /// The minimum duration to remember handshake MAC1s for replay detection
+    ///
+    /// # Note
+    /// MAC1s are remembered in two rotating generations, so a MAC1 is remembered for at least this duration and at most
+    /// twice this duration, unless [`Self::WGPROXY_REPLAY_CAPACITY`] forces an early rotation.
+    ///
+    /// # Example
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to
+    /// [`Self::WGPROXY_REPLAY_WINDOW_DEFAULT`]
+    pub WGPROXY_REPLAY_WINDOW: Duration,
+    /// The maximum amount of remembered handshake MAC1s
+    ///
+    /// # Note
+    /// If the replay cache is full, the oldest generation of MAC1s is forgotten early to bound the memory usage.
+    ///
+    /// # Example
+    /// A positive integer value, defaults to [`Self::WGPROXY_REPLAY_CAPACITY_DEFAULT`]
+    pub WGPROXY_REPLAY_CAPACITY: usize,
+    /// The policy whether a handshake from a new client may take over an existing session
+    ///
+    /// # Possible Values
+    /// Possible values are:
+    /// - `never`: An existing session is never taken over
+    /// - `idle:<duration>`: An existing session is taken over if its client has not sent any packet within the given
+    ///   duration, e.g. `idle:30s` or `idle:30`
+    /// - `always`: An existing session is always taken over by a valid handshake with a fresh MAC1
+    ///
+    /// # Example
+    /// A policy value, defaults to [`Self::WGPROXY_TAKEOVER_DEFAULT`]
+    pub WGPROXY_TAKEOVER: TakeoverPolicy,
+    /// The maximum amount of concurrent sessions
+    ///
+    /// # Example
+    /// A positive integer value, defaults to [`Self::WGPROXY_MAX_SESSIONS_DEFAULT`]
+    pub WGPROXY_MAX_SESSIONS: usize,
+    /// The policy which session to evict if [`Self::WGPROXY_MAX_SESSIONS`] has been reached
+    ///
+    /// # Possible Values
+    /// Possible values are:
+    /// - `reject`: New sessions are rejected, unless the least recently active session may be taken over according to
+    ///   [`Self::WGPROXY_TAKEOVER`]
+    /// - `lru`: The least recently active session is evicted
+    /// - `pending`: The least recently active session that has not completed a handshake yet is evicted; if there is
+    ///   no such session, `reject` applies
+    ///
+    /// # Example
+    /// A policy value, defaults to [`Self::WGPROXY_EVICTION_DEFAULT`]
+    pub WGPROXY_EVICTION: EvictionPolicy,
+    /// The maximum amount of concurrent sessions per client address prefix
+    ///
+    /// # Example
+    /// A positive integer value or `0` to disable the limit, defaults to
+    /// [`Self::WGPROXY_MAX_SESSIONS_PER_PREFIX_DEFAULT`]
+    pub WGPROXY_MAX_SESSIONS_PER_PREFIX: usize,
+    /// The IPv4 prefix length to group client addresses for [`Self::WGPROXY_MAX_SESSIONS_PER_PREFIX`]
+    ///
+    /// # Example
+    /// An integer value from `0` to `32`, defaults to [`Self::WGPROXY_PREFIX_V4_DEFAULT`]
+    pub WGPROXY_PREFIX_V4: u8,
+    /// The IPv6 prefix length to group client addresses for [`Self::WGPROXY_MAX_SESSIONS_PER_PREFIX`]
+    ///
+    /// # Example
+    /// An integer value from `0` to `128`, defaults to [`Self::WGPROXY_PREFIX_V6_DEFAULT`]
+    pub WGPROXY_PREFIX_V6: u8,
+    /// The packet I/O backend
+    ///
+    /// # Possible Values
+    /// Possible values are:
+    /// - `auto`: Uses the best backend available on the current platform, and falls back to `portable` if necessary
+    /// - `portable`: Receives and sends one packet per syscall
+    /// - `mmsg`: Receives and sends packets in batches via `recvmmsg`/`sendmmsg` (Linux only)
+    /// - `io-uring`: Receives and sends packets via `io_uring` with registered buffers, and falls back to `mmsg` or
+    ///   `portable` if `io_uring` is not available (Linux only, requires the `io-uring` cargo feature)
+    ///
+    /// # Example
+    /// A backend value, defaults to [`Self::WGPROXY_BACKEND_DEFAULT`]
+    pub WGPROXY_BACKEND: Backend,
+    /// The maximum amount of packets to receive or send per syscall for batching backends
+    ///
+    /// # Note
+    /// For the `io-uring` backend, this is the amount of concurrently queued receives.
+    ///
+    /// # Example
+    /// A positive integer value, defaults to [`Self::WGPROXY_BATCH_SIZE_DEFAULT`]
+    pub WGPROXY_BATCH_SIZE: usize,
+    /// Whether to use UDP GRO/GSO offloading with the `mmsg` backend
+    ///
+    /// # Note
+    /// If enabled, the kernel coalesces consecutive datagrams from the same source into super-packets (`UDP_GRO`), and
+    /// super-packets whose segments all go to the same destination are forwarded with a single send (`UDP_SEGMENT`). If
+    /// the kernel does not support offloading, the relay gracefully falls back to unsegmented receives and sends.
+    ///
+    /// # Example
+    /// `true` or `false`, defaults to [`Self::WGPROXY_OFFLOAD_DEFAULT`]
+    pub WGPROXY_OFFLOAD: bool,
+    /// The amount of worker threads
+    ///
+    /// # Note
+    /// If more than one worker is configured, each worker gets its own `SO_REUSEPORT` socket on
+    /// [`Self::WGPROXY_LISTEN`] and the kernel distributes the inbound packets across them (Linux only). All workers
+    /// share the same session table and replay cache, which is only locked for routing, so that the packet I/O scales
+    /// with the amount of workers.
+    ///
+    /// # Example
+    /// A positive integer value, defaults to [`Self::WGPROXY_WORKERS_DEFAULT`]
+    pub WGPROXY_WORKERS: usize,
+    /// The path to the state file to persist the replay cache and the active sessions across restarts
+    ///
+    /// # Note
+    /// The relay restores the state on startup and discards stale entries, and snapshots the state every
+    /// [`Self::WGPROXY_STATE_INTERVAL`]. If the state file does not exist, the relay starts with an empty state.
+    ///
+    /// # Example
+    /// A file path, or unset to disable persistence
+    pub WGPROXY_STATE: Option<PathBuf>,
+    /// The interval between two state snapshots
+    ///
+    /// # Example
+    /// A positive duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to
+    /// [`Self::WGPROXY_STATE_INTERVAL_DEFAULT`]
+    pub WGPROXY_STATE_INTERVAL: Duration,
+    /// The drain period after a termination signal
+    ///
+    /// # Note
+    /// On `SIGTERM` or `SIGINT`, the relay stops accepting new sessions but keeps forwarding packets of existing sessions
+    /// until all of them have expired or the drain period has elapsed. Afterwards, it logs the final statistics of all
+    /// remaining sessions and exits gracefully.
+    ///
+    /// # Example
+    /// A duration like `90s`, `5m` or `1h30m`, or an integer amount of seconds, defaults to
+    /// [`Self::WGPROXY_DRAIN_DEFAULT`]; `0` exits immediately
+    pub WGPROXY_DRAIN: Duration,
+    /// The path of the Unix socket for zero-downtime upgrades
+    ///
+    /// # Note
+    /// On startup, the relay connects to this socket to take over the listening sockets and the sessions from a running
+    /// relay process, which exits afterwards. If no process is listening, the relay binds its own sockets. In both
+    /// cases, the relay then listens on this socket to hand itself over to the next process. Only processes running as
+    /// root or as the relay user can take over the relay; the socket should nevertheless be placed in a directory that
+    /// is only accessible by the relay user.
+    ///
+    /// # Example
+    /// A file path, or unset to disable socket handover
+    pub WGPROXY_HANDOVER: Option<PathBuf>,
+    /// The user to switch to after the sockets have been bound
+    ///
+    /// # Note
+    /// If set, the relay clears all supplementary groups and capabilities and switches to this user and its primary
+    /// group (or [`Self::WGPROXY_GROUP`]) before it starts forwarding. The relay fails if the switch does not succeed.
+    /// A numeric id without passwd entry requires [`Self::WGPROXY_GROUP`]. Note that the state file and the handover
+    /// socket are accessed as this user.
+    ///
+    /// # Example
+    /// A user name or numeric id, or unset to keep the current user
+    pub WGPROXY_USER: Option<String>,
+    /// The group to switch to after the sockets have been bound
+    ///
+    /// # Example
+    /// A group name or numeric id, or unset to use the primary group of [`Self::WGPROXY_USER`]
+    pub WGPROXY_GROUP: Option<String>,
+    /// Whether and how to sandbox the relay after startup
+    ///
+    /// # Note
+    /// If enabled, the relay installs a seccomp filter that only allows the syscalls needed for forwarding, DNS
+    /// resolution, state snapshots and handover, and a Landlock ruleset that only allows read access to the resolver
+    /// configuration and write access to the directory of [`Self::WGPROXY_STATE`] (Linux only). Any other syscall kills
+    /// the process, unless `errno` is set explicitly to let it fail with `EPERM` instead. If the kernel does not
+    /// support Landlock, only the seccomp filter is installed.
+    ///
+    /// # Example
+    /// `true` or `kill`, `errno` or `false`, defaults to [`Self::WGPROXY_SANDBOX_DEFAULT`]
+    pub WGPROXY_SANDBOX: Sandbox,
 }
 impl Config {
     /// The default listening address if [`Self::WGPROXY_LISTEN`] is not specified
     pub const WGPROXY_LISTEN_DEFAULT: &str = "[::]:51820";
     /// The default timeout in seconds if [`Self::WGPROXY_TIMEOUT`] is not specified
     pub const WGPROXY_TIMEOUT_DEFAULT: &str = "60";
+    /// The default idle policy if [`Self::WGPROXY_IDLE_POLICY`] is not specified
+    pub const WGPROXY_IDLE_POLICY_DEFAULT: &str = "min";
     /// The default loglevel if [`Self::WGPROXY_LOGLEVEL`] is not specified
     pub const WGPROXY_LOGLEVEL_DEFAULT: &str = "1";
+    /// The default amplification packet limit if [`Self::WGPROXY_AMPLIFICATION_LIMIT`] is not specified
+    pub const WGPROXY_AMPLIFICATION_LIMIT_DEFAULT: &str = "0";
+    /// The default amplification ratio if [`Self::WGPROXY_AMPLIFICATION_RATIO`] is not specified
+    pub const WGPROXY_AMPLIFICATION_RATIO_DEFAULT: &str = "3";
+    /// The default replay window in seconds if [`Self::WGPROXY_REPLAY_WINDOW`] is not specified
+    pub const WGPROXY_REPLAY_WINDOW_DEFAULT: &str = "300";
+    /// The default replay cache capacity if [`Self::WGPROXY_REPLAY_CAPACITY`] is not specified
+    pub const WGPROXY_REPLAY_CAPACITY_DEFAULT: &str = "262144";
+    /// The default takeover policy if [`Self::WGPROXY_TAKEOVER`] is not specified
+    pub const WGPROXY_TAKEOVER_DEFAULT: &str = "never";
+    /// The default session limit if [`Self::WGPROXY_MAX_SESSIONS`] is not specified
+    pub const WGPROXY_MAX_SESSIONS_DEFAULT: &str = "1";
+    /// The default eviction policy if [`Self::WGPROXY_EVICTION`] is not specified
+    pub const WGPROXY_EVICTION_DEFAULT: &str = "reject";
+    /// The default per-prefix session limit if [`Self::WGPROXY_MAX_SESSIONS_PER_PREFIX`] is not specified
+    pub const WGPROXY_MAX_SESSIONS_PER_PREFIX_DEFAULT: &str = "0";
+    /// The default IPv4 prefix length if [`Self::WGPROXY_PREFIX_V4`] is not specified
+    pub const WGPROXY_PREFIX_V4_DEFAULT: &str = "24";
+    /// The default IPv6 prefix length if [`Self::WGPROXY_PREFIX_V6`] is not specified
+    pub const WGPROXY_PREFIX_V6_DEFAULT: &str = "64";
+    /// The default I/O backend if [`Self::WGPROXY_BACKEND`] is not specified
+    pub const WGPROXY_BACKEND_DEFAULT: &str = "auto";
+    /// The default batch size if [`Self::WGPROXY_BATCH_SIZE`] is not specified
+    pub const WGPROXY_BATCH_SIZE_DEFAULT: &str = "32";
+    /// The default offloading setting if [`Self::WGPROXY_OFFLOAD`] is not specified
+    pub const WGPROXY_OFFLOAD_DEFAULT: &str = "false";
+    /// The default amount of worker threads if [`Self::WGPROXY_WORKERS`] is not specified
+    pub const WGPROXY_WORKERS_DEFAULT: &str = "1";
+    /// The default state snapshot interval in seconds if [`Self::WGPROXY_STATE_INTERVAL`] is not specified
+    pub const WGPROXY_STATE_INTERVAL_DEFAULT: &str = "60";
+    /// The default drain period in seconds if [`Self::WGPROXY_DRAIN`] is not specified
+    pub const WGPROXY_DRAIN_DEFAULT: &str = "5";
+    /// The default sandboxing setting if [`Self::WGPROXY_SANDBOX`] is not specified
+    pub const WGPROXY_SANDBOX_DEFAULT: &str = "false";
 
     /// Gets the config from the environment
     pub fn from_env() -> Result<Self, Error> {
-        Ok(Config {
-            WGPROXY_SERVER: Self::wgproxy_server()?,
-            WGPROXY_PUBKEY: Self::wgproxy_pubkey()?,
-            WGPROXY_LISTEN: Self::wgproxy_listen()?,
-            WGPROXY_TIMEOUT: Self::wgproxy_timeout()?,
-            WGPROXY_LOGLEVEL: Self::wgproxy_loglevel()?,
+        Self::from_values(&BTreeMap::new())
+    }
+
+    /// Gets the config from the given values, and falls back to the overrides and the environment for missing values
+    ///
+    /// # Note
+    /// The values are keyed by the environment variable names, e.g. `WGPROXY_SERVER`. If several settings are
+    /// invalid, the problem of the first one is returned.
+    pub fn from_values(values: &BTreeMap<String, String>) -> Result<Self, Error> {
+        Self::parse(values, false).map_err(|problems| match problems.into_iter().next() {
+            Some((_, e)) => e,
+            None => error!("Invalid config"),
         })
     }
 
-    /// Parses the `WGPROXY_SERVER` environment variable
-    fn wgproxy_server() -> Result<String, Error> {
-        let address = Self::env("WGPROXY_SERVER", "<unspecified>")?;
+    /// Gets the config like [`Self::from_values`], but validates every setting and reports all problems at once
+    ///
+    /// # Note
+    /// In addition to the parsing, this also reports settings that are syntactically valid but would cause problems at
+    /// runtime, e.g. a timeout of zero or a server that is not reachable from the listening address family.
+    pub fn check(values: &BTreeMap<String, String>) -> Result<Self, Vec<Error>> {
+        Self::parse(values, true)
+            .map_err(|problems| problems.into_iter().map(|(name, e)| error!("{name}: {e}")).collect())
+    }
+
+    /// Parses every setting independently, and returns the config or the problems of all settings with their names
+    ///
+    /// # Note
+    /// If `check` is set, the settings that would only fail at runtime are reported as well, see [`Self::check`].
+    #[allow(non_snake_case, reason = "The settings are parsed into bindings named after their fields")]
+    pub(crate) fn parse(values: &Values, check: bool) -> Result<Self, Vec<(&'static str, Error)>> {
+        /// Parses the given fields in order, and only builds the config if all fields are valid and nothing else has
+        /// been reported
+        macro_rules! fields {
+            ($problems:ident, $($field:ident: $result:expr,)*) => {{
+                $(
+                    let $field = $result.map_err(|e| $problems.push((stringify!($field), e))).ok();
+                )*
+                match ($($field,)*) {
+                    ($(Some($field),)*) if $problems.is_empty() => Ok(Config { $($field,)* }),
+                    _ => Err($problems),
+                }
+            }};
+        }
+
+        // Parse the settings that other settings depend on or that are checked further first
+        let (wg_config, peer) = match Self::wgproxy_wg_config(values) {
+            Ok(Some(path)) => match Peer::load(&path) {
+                Ok(peer) => (Ok(Some(path)), Some(peer)),
+                Err(e) => (Err(e), None),
+            },
+            wg_config => (wg_config, None),
+        };
+        let server = Self::wgproxy_server(values, peer.as_ref());
+        let pubkey = Self::wgproxy_pubkey(values, peer.as_ref());
+        let listen = Self::wgproxy_listen(values);
+        let timeout = Self::wgproxy_timeout(values);
+        let directional = |name, parse: fn(&Values, Duration) -> Result<Duration, Error>| match &timeout {
+            Ok(timeout) => Some(parse(values, *timeout)),
+            // Skip the values that would be inherited from an invalid general timeout
+            Err(_) => Self::env_opt(values, name).transpose().map(|_| parse(values, Duration::ZERO)),
+        };
+        let uplink = directional("WGPROXY_TIMEOUT_UPLINK", Self::wgproxy_timeout_uplink);
+        let downlink = directional("WGPROXY_TIMEOUT_DOWNLINK", Self::wgproxy_timeout_downlink);
+        let state = Self::wgproxy_state(values);
+
+        // Check the settings that would only fail at runtime
+        let mut problems = Vec::new();
+        if check {
+            let timeouts = [
+                ("WGPROXY_TIMEOUT", Some(&timeout)),
+                ("WGPROXY_TIMEOUT_UPLINK", uplink.as_ref()),
+                ("WGPROXY_TIMEOUT_DOWNLINK", downlink.as_ref()),
+            ];
+            let is_zero = |timeout: &Option<&Result<Duration, Error>>| {
+                timeout.is_some_and(|t| t.as_ref().is_ok_and(Duration::is_zero))
+            };
+            for (name, _) in timeouts.into_iter().filter(|(_, timeout)| is_zero(timeout)) {
+                problems.push((name, error!("A timeout of zero expires every session immediately")));
+            }
+            problems.extend(Self::check_runtime(&server, &pubkey, &listen, &state));
+        }
+
+        // A skipped directional timeout is only a placeholder, as the invalid general timeout fails the config anyway
+        let skipped = || Ok(Duration::ZERO);
+        fields! {
+            problems,
+            WGPROXY_WG_CONFIG: wg_config,
+            WGPROXY_SERVER: server,
+            WGPROXY_PUBKEY: pubkey,
+            WGPROXY_LISTEN: listen,
+            WGPROXY_ALLOW: Self::wgproxy_prefixes(values, "WGPROXY_ALLOW"),
+            WGPROXY_DENY: Self::wgproxy_prefixes(values, "WGPROXY_DENY"),
+            WGPROXY_TIMEOUT: timeout,
+            WGPROXY_TIMEOUT_UPLINK: uplink.unwrap_or_else(skipped),
+            WGPROXY_TIMEOUT_DOWNLINK: downlink.unwrap_or_else(skipped),
+            WGPROXY_IDLE_POLICY: Self::wgproxy_idle_policy(values),
+            WGPROXY_REKEY_GRACE: Self::wgproxy_rekey_grace(values),
+            WGPROXY_LOGLEVEL: Self::wgproxy_loglevel(values),
+            WGPROXY_AMPLIFICATION_LIMIT: Self::wgproxy_amplification_limit(values),
+            WGPROXY_AMPLIFICATION_RATIO: Self::wgproxy_amplification_ratio(values),
+            WGPROXY_REPLAY_WINDOW: Self::wgproxy_replay_window(values),
+            WGPROXY_REPLAY_CAPACITY: Self::wgproxy_replay_capacity(values),
+            WGPROXY_TAKEOVER: Self::wgproxy_takeover(values),
+            WGPROXY_MAX_SESSIONS: Self::wgproxy_max_sessions(values),
+            WGPROXY_EVICTION: Self::wgproxy_eviction(values),
+            WGPROXY_MAX_SESSIONS_PER_PREFIX: Self::wgproxy_max_sessions_per_prefix(values),
+            WGPROXY_PREFIX_V4: Self::wgproxy_prefix(values, "WGPROXY_PREFIX_V4", Self::WGPROXY_PREFIX_V4_DEFAULT, 32),
+            WGPROXY_PREFIX_V6: Self::wgproxy_prefix(values, "WGPROXY_PREFIX_V6", Self::WGPROXY_PREFIX_V6_DEFAULT, 128),
+            WGPROXY_BACKEND: Self::wgproxy_backend(values),
+            WGPROXY_BATCH_SIZE: Self::wgproxy_batch_size(values),
+            WGPROXY_OFFLOAD: Self::wgproxy_offload(values),
+            WGPROXY_WORKERS: Self::wgproxy_workers(values),
+            WGPROXY_STATE: state,
+            WGPROXY_STATE_INTERVAL: Self::wgproxy_state_interval(values),
+            WGPROXY_DRAIN: Self::wgproxy_drain(values),
+            WGPROXY_HANDOVER: Self::wgproxy_handover(values),
+            WGPROXY_USER: Self::wgproxy_user(values),
+            WGPROXY_GROUP: Self::wgproxy_group(values),
+            WGPROXY_SANDBOX: Self::wgproxy_sandbox(values),
+        }
+    }
+
+    /// Checks the settings that are syntactically valid but would fail at runtime
+    fn check_runtime(
+        server: &Result<String, Error>,
+        pubkey: &Result<Vec<PublicKey>, Error>,
+        listen: &Result<SocketAddr, Error>,
+        state: &Result<Option<PathBuf>, Error>,
+    ) -> Vec<(&'static str, Error)> {
+        let mut problems = Vec::new();
+        if let (Ok(server), Ok(listen)) = (&server, &listen)
+            && let Err(e) = Self::check_server_family(server, listen)
+        {
+            problems.push(("WGPROXY_SERVER", e));
+        }
+        if let Ok(Some(parent)) = state.as_ref().map(|state| state.as_deref().and_then(Path::parent))
+            && !parent.as_os_str().is_empty()
+            && !parent.is_dir()
+        {
+            problems.push(("WGPROXY_STATE", error!("The directory {} does not exist", parent.display())));
+        }
+        let now = SystemTime::now();
+        if let Ok(pubkey) = &pubkey
+            && !pubkey.iter().any(|public_key| public_key.is_valid_at(now))
+        {
+            problems.push(("WGPROXY_PUBKEY", error!("No public key is currently valid")));
+        }
+        problems
+    }
+
+    /// Ensures that the server can be reached from the address family of the given listening address
+    fn check_server_family(server: &str, listen: &SocketAddr) -> Result<(), Error> {
+        let addresses: Vec<SocketAddr> = server.to_socket_addrs()?.collect();
+        let reachable = match listen.ip() {
+            // An IPv4 socket can only reach IPv4 servers
+            IpAddr::V4(_) => addresses.iter().any(SocketAddr::is_ipv4),
+            // An unspecified IPv6 socket is usually dual-stack and can also reach IPv4 servers via mapped addresses
+            IpAddr::V6(address) if address.is_unspecified() => !addresses.is_empty(),
+            IpAddr::V6(_) => addresses.iter().any(SocketAddr::is_ipv6),
+        };
+        match reachable {
+            true => Ok(()),
+            false => Err(error!("Server {server} is not reachable from listening address {listen}")),
+        }
+    }
+
+    /// Parses the `WGPROXY_SERVER` environment variable, or falls back to the endpoint of the wg-quick config
+    fn wgproxy_server(values: &Values, peer: Option<&Peer>) -> Result<String, Error> {
+        let address = match (Self::env_opt(values, "WGPROXY_SERVER")?, peer) {
+            (Some(address), _) => address,
+            (None, Some(Peer { endpoint: Some(endpoint), .. })) => endpoint.clone(),
+            (None, Some(_)) => return Err(error!("Missing Endpoint in [Peer] section of WireGuard config")),
+            (None, None) => "<unspecified>".to_string(),
+        };
         let Some(_) = address.to_socket_addrs()?.next() else {
             // The address cannot be resolved; fail fast
             return Err(error!(r#"Failed to resolve server address {address}"#));
         };
 
-        // Retain the address as string so we can periodically re-resolve DNS names for to catch e.g. dynDNS or load
+        // Retain the address as string so the relay can periodically re-resolve DNS names to catch e.g. dynDNS or load
         //  balancing
         Ok(address.to_string())
     }
 
-    /// Parses the `WGPROXY_PUBKEY` environment variable
-    fn wgproxy_pubkey() -> Result<[u8; 32], Error> {
-        // Decode pubkey
-        let pubkey = Self::env("WGPROXY_PUBKEY", "<unspecified>")?;
-        let binary = Base64::decode_vec(&pubkey)
-            .map_err(|e| error!(with: e, r#"Failed to base64-decode public key "{pubkey}""#))?;
+    /// Parses the `WGPROXY_PUBKEY` environment variable, or falls back to the public key of the wg-quick config
+    fn wgproxy_pubkey(values: &Values, peer: Option<&Peer>) -> Result<Vec<PublicKey>, Error> {
+        let pubkeys = match (Self::env_opt(values, "WGPROXY_PUBKEY")?, peer) {
+            (Some(pubkeys), _) => pubkeys,
+            (None, Some(peer)) => peer.public_key.clone(),
+            (None, None) => "<unspecified>".to_string(),
+        };
+        pubkeys.split(',').map(PublicKey::from_str).collect()
+    }
 
-        // Ensure the decoded public key is exactly 32 bytes
-        let maybe_binary = <[u8; 32]>::try_from(binary).ok();
-        maybe_binary.ok_or(error!(r#"Invalid public key "{pubkey}""#))
+    /// Parses the `WGPROXY_WG_CONFIG` environment variable if it is set
+    fn wgproxy_wg_config(values: &Values) -> Result<Option<PathBuf>, Error> {
+        let path = Self::env_opt(values, "WGPROXY_WG_CONFIG")?;
+        Ok(path.map(PathBuf::from))
     }
 
     /// Parses the `WGPROXY_LISTEN` environment variable, or falls back to [`Self::WGPROXY_LISTEN_DEFAULT`]
-    fn wgproxy_listen() -> Result<SocketAddr, Error> {
-        let address = Self::env("WGPROXY_LISTEN", Self::WGPROXY_LISTEN_DEFAULT)?;
+    fn wgproxy_listen(values: &Values) -> Result<SocketAddr, Error> {
+        let address = Self::env(values, "WGPROXY_LISTEN", Self::WGPROXY_LISTEN_DEFAULT)?;
         let maybe_address: Result<SocketAddr, _> = address.parse();
         maybe_address.map_err(|e| error!(with: e, r#"Invalid listening address "{address}""#))
     }
 
+    /// Parses a comma-separated prefix list environment variable if it is set
+    fn wgproxy_prefixes(values: &Values, name: &str) -> Result<Vec<Prefix>, Error> {
+        let Some(prefixes) = Self::env_opt(values, name)? else {
+            // The list is empty
+            return Ok(Vec::new());
+        };
+        prefixes.split(',').filter(|prefix| !prefix.trim().is_empty()).map(Prefix::from_str).collect()
+    }
+
     /// Parses the `WGPROXY_TIMEOUT` environment variable, or falls back to [`Self::WGPROXY_TIMEOUT_DEFAULT`]
-    fn wgproxy_timeout() -> Result<Duration, Error> {
-        let seconds = Self::env("WGPROXY_TIMEOUT", Self::WGPROXY_TIMEOUT_DEFAULT)?;
-        let seconds = seconds.parse()?;
-        Ok(Duration::from_secs(seconds))
+    fn wgproxy_timeout(values: &Values) -> Result<Duration, Error> {
+        let timeout = Self::env(values, "WGPROXY_TIMEOUT", Self::WGPROXY_TIMEOUT_DEFAULT)?;
+        Self::duration(&timeout)
+    }
+
+    /// Parses the `WGPROXY_TIMEOUT_UPLINK` environment variable, or falls back to the given timeout
+    fn wgproxy_timeout_uplink(values: &Values, timeout: Duration) -> Result<Duration, Error> {
+        let Some(timeout) = Self::env_opt(values, "WGPROXY_TIMEOUT_UPLINK")? else {
+            // Use the general timeout
+            return Ok(timeout);
+        };
+        Self::duration(&timeout)
+    }
+
+    /// Parses the `WGPROXY_TIMEOUT_DOWNLINK` environment variable, or falls back to the given timeout
+    fn wgproxy_timeout_downlink(values: &Values, timeout: Duration) -> Result<Duration, Error> {
+        let Some(timeout) = Self::env_opt(values, "WGPROXY_TIMEOUT_DOWNLINK")? else {
+            // Use the general timeout
+            return Ok(timeout);
+        };
+        Self::duration(&timeout)
+    }
+
+    /// Parses the `WGPROXY_IDLE_POLICY` environment variable, or falls back to [`Self::WGPROXY_IDLE_POLICY_DEFAULT`]
+    fn wgproxy_idle_policy(values: &Values) -> Result<IdlePolicy, Error> {
+        let policy = Self::env(values, "WGPROXY_IDLE_POLICY", Self::WGPROXY_IDLE_POLICY_DEFAULT)?;
+        match policy.as_ref() {
+            "min" => Ok(IdlePolicy::Min),
+            "max" => Ok(IdlePolicy::Max),
+            "uplink" => Ok(IdlePolicy::Uplink),
+            "downlink" => Ok(IdlePolicy::Downlink),
+            _ => Err(error!(r#"Invalid idle policy "{policy}""#)),
+        }
+    }
+
+    /// Parses the `WGPROXY_REKEY_GRACE` environment variable if it is set
+    fn wgproxy_rekey_grace(values: &Values) -> Result<Option<Duration>, Error> {
+        let Some(grace) = Self::env_opt(values, "WGPROXY_REKEY_GRACE")? else {
+            // Re-key tracking is disabled
+            return Ok(None);
+        };
+        Ok(Some(Self::duration(&grace)?))
     }
 
     /// Parses the `WGPROXY_LOGLEVEL` environment variable, or falls back to [`Self::WGPROXY_LOGLEVEL_DEFAULT`]
-    pub fn wgproxy_loglevel() -> Result<u8, Error> {
-        let loglevel = Self::env("WGPROXY_LOGLEVEL", Self::WGPROXY_LOGLEVEL_DEFAULT)?;
-        Ok(loglevel.parse()?)
+    pub fn wgproxy_loglevel(values: &BTreeMap<String, String>) -> Result<u8, Error> {
+        let loglevel = Self::env(values, "WGPROXY_LOGLEVEL", Self::WGPROXY_LOGLEVEL_DEFAULT)?;
+        match loglevel.as_ref() {
+            "error" => Ok(0),
+            "warn" => Ok(1),
+            "info" => Ok(2),
+            "debug" | "trace" => Ok(3),
+            _ => loglevel.parse::<u8>().map_err(|_| {
+                error!(r#"Invalid log level "{loglevel}"; expected error, warn, info, debug, trace or 0 to 3"#)
+            }),
+        }
+    }
+
+    /// Parses the `WGPROXY_AMPLIFICATION_LIMIT` environment variable, or falls back to
+    /// [`Self::WGPROXY_AMPLIFICATION_LIMIT_DEFAULT`]
+    fn wgproxy_amplification_limit(values: &Values) -> Result<u64, Error> {
+        let limit = Self::env(values, "WGPROXY_AMPLIFICATION_LIMIT", Self::WGPROXY_AMPLIFICATION_LIMIT_DEFAULT)?;
+        Ok(limit.parse()?)
+    }
+
+    /// Parses the `WGPROXY_AMPLIFICATION_RATIO` environment variable, or falls back to
+    /// [`Self::WGPROXY_AMPLIFICATION_RATIO_DEFAULT`]
+    fn wgproxy_amplification_ratio(values: &Values) -> Result<u64, Error> {
+        let ratio = Self::env(values, "WGPROXY_AMPLIFICATION_RATIO", Self::WGPROXY_AMPLIFICATION_RATIO_DEFAULT)?;
+        Ok(ratio.parse()?)
+    }
+
+    /// Parses the `WGPROXY_REPLAY_WINDOW` environment variable, or falls back to
+    /// [`Self::WGPROXY_REPLAY_WINDOW_DEFAULT`]
+    fn wgproxy_replay_window(values: &Values) -> Result<Duration, Error> {
+        let window = Self::env(values, "WGPROXY_REPLAY_WINDOW", Self::WGPROXY_REPLAY_WINDOW_DEFAULT)?;
+        Self::duration(&window)
+    }
+
+    /// Parses the `WGPROXY_REPLAY_CAPACITY` environment variable, or falls back to
+    /// [`Self::WGPROXY_REPLAY_CAPACITY_DEFAULT`]
+    fn wgproxy_replay_capacity(values: &Values) -> Result<usize, Error> {
+        let capacity = Self::env(values, "WGPROXY_REPLAY_CAPACITY", Self::WGPROXY_REPLAY_CAPACITY_DEFAULT)?;
+        match capacity.parse()? {
+            0 => Err(error!(r#"Invalid replay cache capacity "{capacity}""#)),
+            capacity => Ok(capacity),
+        }
+    }
+
+    /// Parses the `WGPROXY_TAKEOVER` environment variable, or falls back to [`Self::WGPROXY_TAKEOVER_DEFAULT`]
+    fn wgproxy_takeover(values: &Values) -> Result<TakeoverPolicy, Error> {
+        let policy = Self::env(values, "WGPROXY_TAKEOVER", Self::WGPROXY_TAKEOVER_DEFAULT)?;
+        match policy.as_ref() {
+            "never" => Ok(TakeoverPolicy::Never),
+            "always" => Ok(TakeoverPolicy::Always),
+            _ => {
+                // Parse the idle duration
+                let Some(idle) = policy.strip_prefix("idle:") else {
+                    return Err(error!(r#"Invalid takeover policy "{policy}""#));
+                };
+                Ok(TakeoverPolicy::Idle(Self::duration(idle)?))
+            }
+        }
+    }
+
+    /// Parses the `WGPROXY_MAX_SESSIONS` environment variable, or falls back to [`Self::WGPROXY_MAX_SESSIONS_DEFAULT`]
+    fn wgproxy_max_sessions(values: &Values) -> Result<usize, Error> {
+        let max_sessions = Self::env(values, "WGPROXY_MAX_SESSIONS", Self::WGPROXY_MAX_SESSIONS_DEFAULT)?;
+        match max_sessions.parse()? {
+            0 => Err(error!(r#"Invalid session limit "{max_sessions}""#)),
+            max_sessions => Ok(max_sessions),
+        }
+    }
+
+    /// Parses the `WGPROXY_EVICTION` environment variable, or falls back to [`Self::WGPROXY_EVICTION_DEFAULT`]
+    fn wgproxy_eviction(values: &Values) -> Result<EvictionPolicy, Error> {
+        let policy = Self::env(values, "WGPROXY_EVICTION", Self::WGPROXY_EVICTION_DEFAULT)?;
+        match policy.as_ref() {
+            "reject" => Ok(EvictionPolicy::Reject),
+            "lru" => Ok(EvictionPolicy::LeastRecentlyActive),
+            "pending" => Ok(EvictionPolicy::Pending),
+            _ => Err(error!(r#"Invalid eviction policy "{policy}""#)),
+        }
+    }
+
+    /// Parses the `WGPROXY_MAX_SESSIONS_PER_PREFIX` environment variable, or falls back to
+    /// [`Self::WGPROXY_MAX_SESSIONS_PER_PREFIX_DEFAULT`]
+    fn wgproxy_max_sessions_per_prefix(values: &Values) -> Result<usize, Error> {
+        let max_sessions =
+            Self::env(values, "WGPROXY_MAX_SESSIONS_PER_PREFIX", Self::WGPROXY_MAX_SESSIONS_PER_PREFIX_DEFAULT)?;
+        Ok(max_sessions.parse()?)
+    }
+
+    /// Parses a prefix length environment variable with the given upper bound, or falls back to the given default
+    fn wgproxy_prefix(values: &Values, name: &str, default: &'static str, max: u8) -> Result<u8, Error> {
+        let prefix = Self::env(values, name, default)?;
+        match prefix.parse()? {
+            length if length <= max => Ok(length),
+            _ => Err(error!(r#"Invalid prefix length "{prefix}""#)),
+        }
+    }
+
+    /// Parses the `WGPROXY_BACKEND` environment variable, or falls back to [`Self::WGPROXY_BACKEND_DEFAULT`]
+    fn wgproxy_backend(values: &Values) -> Result<Backend, Error> {
+        let backend = Self::env(values, "WGPROXY_BACKEND", Self::WGPROXY_BACKEND_DEFAULT)?;
+        match backend.as_ref() {
+            "auto" => Ok(Backend::Auto),
+            "portable" => Ok(Backend::Portable),
+            "mmsg" => Ok(Backend::Mmsg),
+            "io-uring" => Ok(Backend::IoUring),
+            _ => Err(error!(r#"Invalid backend "{backend}""#)),
+        }
+    }
+
+    /// Parses the `WGPROXY_BATCH_SIZE` environment variable, or falls back to [`Self::WGPROXY_BATCH_SIZE_DEFAULT`]
+    fn wgproxy_batch_size(values: &Values) -> Result<usize, Error> {
+        let batch_size = Self::env(values, "WGPROXY_BATCH_SIZE", Self::WGPROXY_BATCH_SIZE_DEFAULT)?;
+        match batch_size.parse()? {
+            0 => Err(error!(r#"Invalid batch size "{batch_size}""#)),
+            batch_size => Ok(batch_size),
+        }
+    }
+
+    /// Parses the `WGPROXY_OFFLOAD` environment variable, or falls back to [`Self::WGPROXY_OFFLOAD_DEFAULT`]
+    fn wgproxy_offload(values: &Values) -> Result<bool, Error> {
+        let offload = Self::env(values, "WGPROXY_OFFLOAD", Self::WGPROXY_OFFLOAD_DEFAULT)?;
+        Ok(offload.parse()?)
+    }
+
+    /// Parses the `WGPROXY_WORKERS` environment variable, or falls back to [`Self::WGPROXY_WORKERS_DEFAULT`]
+    fn wgproxy_workers(values: &Values) -> Result<usize, Error> {
+        let workers = Self::env(values, "WGPROXY_WORKERS", Self::WGPROXY_WORKERS_DEFAULT)?;
+        match workers.parse()? {
+            0 => Err(error!(r#"Invalid worker count "{workers}""#)),
+            workers => Ok(workers),
+        }
+    }
+
+    /// Parses the `WGPROXY_STATE` environment variable if it is set
+    fn wgproxy_state(values: &Values) -> Result<Option<PathBuf>, Error> {
+        let state = Self::env_opt(values, "WGPROXY_STATE")?;
+        Ok(state.map(PathBuf::from))
+    }
+
+    /// Parses the `WGPROXY_STATE_INTERVAL` environment variable, or falls back to
+    /// [`Self::WGPROXY_STATE_INTERVAL_DEFAULT`]
+    fn wgproxy_state_interval(values: &Values) -> Result<Duration, Error> {
+        let interval = Self::env(values, "WGPROXY_STATE_INTERVAL", Self::WGPROXY_STATE_INTERVAL_DEFAULT)?;
+        match Self::duration(&interval)? {
+            Duration::ZERO => Err(error!(r#"Invalid state snapshot interval "{interval}""#)),
+            interval => Ok(interval),
+        }
+    }
+
+    /// Parses the `WGPROXY_DRAIN` environment variable, or falls back to [`Self::WGPROXY_DRAIN_DEFAULT`]
+    fn wgproxy_drain(values: &Values) -> Result<Duration, Error> {
+        let drain = Self::env(values, "WGPROXY_DRAIN", Self::WGPROXY_DRAIN_DEFAULT)?;
+        Self::duration(&drain)
+    }
+
+    /// Parses the `WGPROXY_HANDOVER` environment variable if it is set
+    fn wgproxy_handover(values: &Values) -> Result<Option<PathBuf>, Error> {
+        let path = Self::env_opt(values, "WGPROXY_HANDOVER")?;
+        Ok(path.map(PathBuf::from))
+    }
+
+    /// Parses the `WGPROXY_USER` environment variable if it is set
+    fn wgproxy_user(values: &Values) -> Result<Option<String>, Error> {
+        Self::env_opt(values, "WGPROXY_USER")
+    }
+
+    /// Parses the `WGPROXY_GROUP` environment variable if it is set
+    fn wgproxy_group(values: &Values) -> Result<Option<String>, Error> {
+        Self::env_opt(values, "WGPROXY_GROUP")
+    }
+
+    /// Parses the `WGPROXY_SANDBOX` environment variable, or falls back to [`Self::WGPROXY_SANDBOX_DEFAULT`]
+    fn wgproxy_sandbox(values: &Values) -> Result<Sandbox, Error> {
+        let sandbox = Self::env(values, "WGPROXY_SANDBOX", Self::WGPROXY_SANDBOX_DEFAULT)?;
+        match sandbox.as_ref() {
+            "false" => Ok(Sandbox::Off),
+            "true" | "kill" => Ok(Sandbox::Kill),
+            "errno" => Ok(Sandbox::Errno),
+            _ => Err(error!(r#"Invalid sandbox mode "{sandbox}"; expected true, kill, errno or false"#)),
+        }
+    }
+
+    /// Parses the `WGPROXY_CONFIG` environment variable if it is set
+    ///
+    /// # Note
+    /// If set, the relays are loaded from the given config file via [`Self::from_file`] instead of the environment.
+    pub fn wgproxy_config() -> Result<Option<PathBuf>, Error> {
+        let path = Self::env_opt(&Values::new(), "WGPROXY_CONFIG")?;
+        Ok(path.map(PathBuf::from))
+    }
+
+    /// Parses a duration like `90s`, `5m` or `1h30m` with the units `ms`, `s`, `m`, `h` and `d`, or an integer amount
+    /// of seconds
+    fn duration(duration: &str) -> Result<Duration, Error> {
+        let invalid =
+            || error!(r#"Invalid duration "{duration}"; expected e.g. "90s", "5m", "1h30m" or seconds like "90""#);
+        if let Ok(seconds) = duration.parse() {
+            // Plain integers are seconds
+            return Ok(Duration::from_secs(seconds));
+        }
+
+        // Parse the components
+        let (mut total, mut rest) = (Duration::ZERO, duration);
+        while !rest.is_empty() {
+            let digits = rest.find(|char: char| !char.is_ascii_digit()).unwrap_or(rest.len());
+            let (amount, tail) = rest.split_at(digits);
+            let units = tail.find(|char: char| char.is_ascii_digit()).unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(units);
+            let amount: u64 = amount.parse().map_err(|_| invalid())?;
+            let component = match unit {
+                "ms" => Duration::from_millis(amount),
+                "s" => Duration::from_secs(amount),
+                "m" => Duration::from_secs(amount.checked_mul(60).ok_or_else(invalid)?),
+                "h" => Duration::from_secs(amount.checked_mul(60 * 60).ok_or_else(invalid)?),
+                "d" => Duration::from_secs(amount.checked_mul(24 * 60 * 60).ok_or_else(invalid)?),
+                _ => return Err(invalid()),
+            };
+            total = total.checked_add(component).ok_or_else(invalid)?;
+            rest = tail;
+        }
+
+        // Reject empty durations
+        match duration.is_empty() {
+            true => Err(invalid()),
+            false => Ok(total),
+        }
+    }
+
+    /// Overrides the environment variable with the given name for this and all subsequent config loads
+    ///
+    /// # Note
+    /// Overrides take precedence over the environment and also apply to config reloads.
+    pub fn set_override(name: &str, value: &str) -> Result<(), Error> {
+        let mut overrides = OVERRIDES.write().map_err(|_| error!("Config overrides are poisoned"))?;
+        overrides.insert(name.to_string(), value.to_string());
+        Ok(())
     }
 
     /// Gets the environment variable with the given name or returns the default value
-    fn env(name: &str, default: &'static str) -> Result<Cow<'static, str>, Error> {
+    fn env(values: &Values, name: &str, default: &'static str) -> Result<Cow<'static, str>, Error> {
+        match Self::env_opt(values, name)? {
+            Some(value) => Ok(Cow::Owned(value)),
+            None => Ok(Cow::Borrowed(default)),
+        }
+    }
+
+    /// Gets the value, the override or the environment variable with the given name if it is set
+    ///
+    /// # Note
+    /// If the environment variable `<name>_FILE` is set instead, the trimmed contents of the given file are used.
+    fn env_opt(values: &Values, name: &str) -> Result<Option<String>, Error> {
+        // Explicit values and overrides take precedence
+        if let Some(value) = values.get(name) {
+            return Ok(Some(value.clone()));
+        }
+        let overrides = OVERRIDES.read().map_err(|_| error!("Config overrides are poisoned"))?;
+        if let Some(value) = overrides.get(name) {
+            return Ok(Some(value.clone()));
+        }
+
+        // Fall back to the environment, where the value may also be read from a file, e.g. a Docker secret
+        let file_name = format!("{name}_FILE");
+        match (Self::env_var(name)?, Self::env_var(&file_name)?) {
+            (Some(_), Some(_)) => Err(error!(r#"Only one of "{name}" and "{file_name}" may be set"#)),
+            (Some(value), None) => Ok(Some(value)),
+            (None, Some(path)) => {
+                let value = fs::read_to_string(&path)
+                    .map_err(|e| error!(with: e, r#"Failed to read "{file_name}" from {path}"#))?;
+                Ok(Some(value.trim().to_string()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Gets the environment variable with the given name if it is set
+    fn env_var(name: &str) -> Result<Option<String>, Error> {
         match env::var(name) {
-            Ok(value) => Ok(Cow::Owned(value)),
-            Err(VarError::NotPresent) => Ok(Cow::Borrowed(default)),
+            Ok(value) => Ok(Some(value)),
+            Err(VarError::NotPresent) => Ok(None),
             Err(e) => Err(error!(with: e, r#"Invalid environment variable "{name}""#)),
         }
     }
 }
 impl Display for Config {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        // Re-encode the public key to display them
-        let pubkey = Base64::encode_string(&self.WGPROXY_PUBKEY);
+        // Re-encode the public keys and prefixes to display them
+        let pubkeys: Vec<_> = self.WGPROXY_PUBKEY.iter().map(PublicKey::to_string).collect();
+        let allow: Vec<_> = self.WGPROXY_ALLOW.iter().map(Prefix::to_string).collect();
+        let deny: Vec<_> = self.WGPROXY_DENY.iter().map(Prefix::to_string).collect();
 
         // Format struct
         f.debug_struct("Config")
             .field("WGPROXY_SERVER", &self.WGPROXY_SERVER)
-            .field("WGPROXY_PUBKEY", &pubkey)
+            .field("WGPROXY_PUBKEY", &pubkeys)
+            .field("WGPROXY_WG_CONFIG", &self.WGPROXY_WG_CONFIG)
             .field("WGPROXY_LISTEN", &self.WGPROXY_LISTEN)
+            .field("WGPROXY_ALLOW", &allow)
+            .field("WGPROXY_DENY", &deny)
             .field("WGPROXY_TIMEOUT", &self.WGPROXY_TIMEOUT)
+            .field("WGPROXY_TIMEOUT_UPLINK", &self.WGPROXY_TIMEOUT_UPLINK)
+            .field("WGPROXY_TIMEOUT_DOWNLINK", &self.WGPROXY_TIMEOUT_DOWNLINK)
+            .field("WGPROXY_IDLE_POLICY", &self.WGPROXY_IDLE_POLICY)
+            .field("WGPROXY_REKEY_GRACE", &self.WGPROXY_REKEY_GRACE)
             .field("WGPROXY_LOGLEVEL", &self.WGPROXY_LOGLEVEL)
+            .field("WGPROXY_AMPLIFICATION_LIMIT", &self.WGPROXY_AMPLIFICATION_LIMIT)
+            .field("WGPROXY_AMPLIFICATION_RATIO", &self.WGPROXY_AMPLIFICATION_RATIO)
+            .field("WGPROXY_REPLAY_WINDOW", &self.WGPROXY_REPLAY_WINDOW)
+            .field("WGPROXY_REPLAY_CAPACITY", &self.WGPROXY_REPLAY_CAPACITY)
+            .field("WGPROXY_TAKEOVER", &self.WGPROXY_TAKEOVER)
+            .field("WGPROXY_MAX_SESSIONS", &self.WGPROXY_MAX_SESSIONS)
+            .field("WGPROXY_EVICTION", &self.WGPROXY_EVICTION)
+            .field("WGPROXY_MAX_SESSIONS_PER_PREFIX", &self.WGPROXY_MAX_SESSIONS_PER_PREFIX)
+            .field("WGPROXY_PREFIX_V4", &self.WGPROXY_PREFIX_V4)
+            .field("WGPROXY_PREFIX_V6", &self.WGPROXY_PREFIX_V6)
+            .field("WGPROXY_BACKEND", &self.WGPROXY_BACKEND)
+            .field("WGPROXY_BATCH_SIZE", &self.WGPROXY_BATCH_SIZE)
+            .field("WGPROXY_OFFLOAD", &self.WGPROXY_OFFLOAD)
+            .field("WGPROXY_WORKERS", &self.WGPROXY_WORKERS)
+            .field("WGPROXY_STATE", &self.WGPROXY_STATE)
+            .field("WGPROXY_STATE_INTERVAL", &self.WGPROXY_STATE_INTERVAL)
+            .field("WGPROXY_DRAIN", &self.WGPROXY_DRAIN)
+            .field("WGPROXY_HANDOVER", &self.WGPROXY_HANDOVER)
+            .field("WGPROXY_USER", &self.WGPROXY_USER)
+            .field("WGPROXY_GROUP", &self.WGPROXY_GROUP)
+            .field("WGPROXY_SANDBOX", &self.WGPROXY_SANDBOX)
             .finish()
     }
 }
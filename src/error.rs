@@ -5,6 +5,8 @@ use crate::config::Config;
 use std::backtrace::{Backtrace, BacktraceStatus};
 use std::fmt::{self, Display, Formatter};
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
 use std::{error, io};
 
 /// Creates a new error
@@ -85,6 +87,61 @@ where
     }
 }
 
+/// The reason why a packet has been dropped
+///
+/// # Performance
+/// Rogue packets may arrive at line rate, so unlike [`Error`], a drop reason does not allocate or capture a backtrace. It
+/// is only rendered into text if the log level requires it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The packet does not belong to a session and is not a handshake initiation
+    NoSession,
+    /// The packet is not a well-formed handshake initiation
+    MalformedHandshake,
+    /// The handshake MAC1 does not match the server public key
+    InvalidMac1,
+    /// The handshake MAC1 has already been seen before
+    ReplayedMac1([u8; 16]),
+    /// The client address is not permitted by the access control lists
+    Denied(IpAddr),
+    /// The session limit for the given client address prefix has been reached
+    PrefixLimit(IpAddr),
+    /// The session limit has been reached
+    SessionLimit,
+    /// The relay is draining and does not accept new sessions
+    Draining,
+    /// The server packet has a receiver index that does not belong to any session
+    UnknownIndex {
+        /// The receiver index
+        index: u32,
+        /// The server address
+        source: SocketAddr,
+    },
+    /// The packet source does not belong to the session
+    UnknownSource(SocketAddr),
+    /// Forwarding the packet to the unverified client would exceed the amplification budget
+    Amplification(SocketAddr),
+}
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::NoSession => write!(f, "Cannot forward packet without valid session"),
+            Self::MalformedHandshake => write!(f, "Packet is not a handshake initiation packet"),
+            Self::InvalidMac1 => write!(f, "MAC1 does not match any server public key"),
+            Self::ReplayedMac1(mac) => {
+                write!(f, "MAC1 {:032x} has already been seen before", u128::from_be_bytes(*mac))
+            }
+            Self::Denied(client) => write!(f, "Client {client} is not permitted to start a session"),
+            Self::PrefixLimit(prefix) => write!(f, "Session limit for prefix {prefix} has been reached"),
+            Self::SessionLimit => write!(f, "Session limit has been reached"),
+            Self::Draining => write!(f, "Relay is draining and does not accept new sessions"),
+            Self::UnknownIndex { index, source } => write!(f, "Unknown receiver index {index:08x} from {source}"),
+            Self::UnknownSource(source) => write!(f, "Unknown packet from {source}"),
+            Self::Amplification(client) => write!(f, "Dropping packet to unverified client {client}"),
+        }
+    }
+}
+
 /// A helper trait for stuff that can be logged
 pub trait Loggable
 where
@@ -97,7 +154,7 @@ where
             // This instance should not be logged
             return self;
         };
-        let true = severity <= LOGLEVEL.get() else {
+        let true = severity <= LOGLEVEL.load(Ordering::Relaxed) else {
             // Log level is not verbose enough
             return self;
         };
@@ -147,7 +204,20 @@ impl Loggable for Error {
         Loggable::write(&error, sink)
     }
 }
-impl<T> Loggable for Result<T, Error> {
+impl Loggable for DropReason {
+    fn write(&self, sink: &mut dyn Write) -> Result<(), io::Error> {
+        write!(sink, "{self}")
+    }
+}
+impl Loggable for fmt::Arguments<'_> {
+    fn write(&self, sink: &mut dyn Write) -> Result<(), io::Error> {
+        write!(sink, "{self}")
+    }
+}
+impl<T, E> Loggable for Result<T, E>
+where
+    E: Loggable,
+{
     fn skip(&self) -> bool {
         self.is_ok()
     }
@@ -155,6 +225,7 @@ impl<T> Loggable for Result<T, Error> {
     fn write(&self, sink: &mut dyn Write) -> Result<(), io::Error> {
         match self.as_ref() {
             Err(e) => e.write(sink),
+            #[allow(clippy::panic, reason = "`Result::Ok` is always skipped and never written")]
             Ok(_) => panic!("trying to log `Result::Ok` variant"),
         }
     }
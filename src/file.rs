@@ -0,0 +1,307 @@
+//! The config file to run multiple relays from one process
+
+use crate::cli;
+use crate::config::Config;
+use crate::error;
+use crate::error::Error;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// The settings that apply to the whole process and are therefore only permitted in the global table
+const PROCESS_WIDE: [&str; 6] =
+    ["WGPROXY_LOGLEVEL", "WGPROXY_DRAIN", "WGPROXY_HANDOVER", "WGPROXY_USER", "WGPROXY_GROUP", "WGPROXY_SANDBOX"];
+
+/// A table of raw config values keyed by their environment variable names
+#[derive(Debug, Default)]
+struct Table {
+    /// The line number of the table header, or `0` for the global table
+    line: usize,
+    /// The raw values with the line numbers of their settings
+    values: BTreeMap<String, (usize, String)>,
+}
+
+impl Config {
+    /// Loads the configs of all relays from the given config file
+    ///
+    /// # Format
+    /// The config file is a subset of TOML: settings in the global table apply to all relays, and every `[[relay]]`
+    /// table defines a relay. Keys are the environment variable names without the `WGPROXY_` prefix in lowercase, e.g.
+    /// `timeout-uplink` or `timeout_uplink` for `WGPROXY_TIMEOUT_UPLINK`. Values can be strings, integers, booleans or
+    /// single-line arrays of them; arrays are joined with commas. Settings that are missing in the file fall back to
+    /// the command-line flags and the environment.
+    ///
+    /// # Unsupported TOML
+    /// Other tables, dotted keys, inline tables, multi-line strings and arrays, floats and dates are rejected.
+    ///
+    /// # Errors
+    /// The problems of all lines and relays are reported together, one per line.
+    pub fn from_file(path: &Path) -> Result<Vec<Self>, Error> {
+        Self::load_file(path, false).map_err(|mut problems| match problems.len() {
+            1 => problems.remove(0),
+            _ => {
+                let problems: Vec<String> = problems.iter().map(Error::to_string).collect();
+                error!("{}", problems.join("\n"))
+            }
+        })
+    }
+
+    /// Loads the configs of all relays from the given config file like [`Self::from_file`], but validates every relay
+    /// via [`Self::check`] and reports all problems at once
+    pub fn check_file(path: &Path) -> Result<Vec<Self>, Vec<Error>> {
+        Self::load_file(path, true)
+    }
+
+    /// Loads the configs of all relays from the given config file, and collects the problems of all relays
+    ///
+    /// # Note
+    /// The problems are reported with the line of the setting they belong to, or with the line of the `[[relay]]`
+    /// header if the setting is not in the file. If `check` is set, the settings are validated via [`Self::check`] and
+    /// the problems are prefixed with the setting name.
+    fn load_file(path: &Path, check: bool) -> Result<Vec<Self>, Vec<Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| vec![error!(with: e, "Failed to read config file {}", path.display())])?;
+        let (global, relays) = parse(&text).map_err(|problems| {
+            let path = path.display();
+            problems.into_iter().map(|e| error!("Invalid config file {path}:{e}")).collect::<Vec<_>>()
+        })?;
+        if relays.is_empty() {
+            return Err(vec![error!("Invalid config file {}: No [[relay]] tables defined", path.display())]);
+        }
+
+        // Load the relays on top of the global table
+        let (mut configs, mut problems): (Vec<Self>, _) = (Vec::new(), Vec::new());
+        let mut reported = BTreeSet::new();
+        for relay in relays {
+            let mut settings = global.values.clone();
+            settings.extend(relay.values);
+            let values = settings.iter().map(|(name, (_, value))| (name.clone(), value.clone())).collect();
+            let line = |name: &str| settings.get(name).map_or(relay.line, |(line, _)| *line);
+
+            // Settings from the global table are shared by all relays, so their problems are only reported once
+            let mut report = |line: usize, problem: String| {
+                let problem = error!("Invalid config file {}:{line}: {problem}", path.display());
+                if reported.insert(problem.error.clone()) {
+                    problems.push(problem);
+                }
+            };
+            let config = match Self::parse(&values, check) {
+                Ok(config) => config,
+                Err(errors) => {
+                    for (name, e) in errors {
+                        match check {
+                            true => report(line(name), format!("{name}: {e}")),
+                            false => report(line(name), e.to_string()),
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            // Relays must not share their sockets or their state files
+            let conflict = configs.iter().find_map(|other| {
+                let same_state = other.WGPROXY_STATE.is_some() && other.WGPROXY_STATE == config.WGPROXY_STATE;
+                if other.WGPROXY_LISTEN == config.WGPROXY_LISTEN {
+                    Some(("WGPROXY_LISTEN", "listening address"))
+                } else {
+                    same_state.then_some(("WGPROXY_STATE", "state file"))
+                }
+            });
+            if let Some((name, conflict)) = conflict {
+                report(line(name), format!("Duplicate {conflict}"));
+            }
+            configs.push(config);
+        }
+
+        // A handover can only pass the sockets of a single relay
+        if configs.len() > 1 && configs.iter().any(|config| config.WGPROXY_HANDOVER.is_some()) {
+            problems.push(error!("Invalid config file {}: Handover requires a single relay", path.display()));
+        }
+        match problems.is_empty() {
+            true => Ok(configs),
+            false => Err(problems),
+        }
+    }
+}
+
+/// Parses the config file into the global table and the relay tables, and collects the problems of all lines
+///
+/// # Note
+/// The errors are prefixed with the line number.
+fn parse(text: &str) -> Result<(Table, Vec<Table>), Vec<Error>> {
+    let (mut global, mut relays, mut problems) = (Table::default(), Vec::<Table>::new(), Vec::new());
+    let mut is_unsupported_table = false;
+    for (index, line) in text.lines().enumerate() {
+        let number = index.saturating_add(1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            // Skip empty lines and comments
+            continue;
+        }
+
+        // Start a new table; the settings of unsupported tables are skipped
+        if line.starts_with('[') {
+            let header = strip_comment(line).trim_end();
+            is_unsupported_table = header != "[[relay]]";
+            match is_unsupported_table {
+                false => relays.push(Table { line: number, values: BTreeMap::new() }),
+                true => problems.push(error!(r#"{number}: Unsupported table "{header}"; expected "[[relay]]""#)),
+            }
+            continue;
+        }
+        if is_unsupported_table {
+            continue;
+        }
+
+        // Parse the setting
+        let (name, value) = match parse_setting(line) {
+            Ok(setting) => setting,
+            Err(e) => {
+                problems.push(error!("{number}: {e}"));
+                continue;
+            }
+        };
+        let table = match relays.last_mut() {
+            Some(_) if PROCESS_WIDE.contains(&name.as_str()) => {
+                problems
+                    .push(error!(r#"{number}: "{name}" applies to all relays and must be set in the global table"#));
+                continue;
+            }
+            Some(relay) => relay,
+            None => &mut global,
+        };
+        if table.values.insert(name.clone(), (number, value)).is_some() {
+            problems.push(error!(r#"{number}: Duplicate setting "{name}""#));
+        }
+    }
+    match problems.is_empty() {
+        true => Ok((global, relays)),
+        false => Err(problems),
+    }
+}
+
+/// Parses a `key = value` line into the environment variable name and the raw value
+fn parse_setting(line: &str) -> Result<(String, String), Error> {
+    let Some((key, value)) = line.split_once('=') else {
+        return Err(error!(r#"Invalid line "{line}"; expected "key = value""#));
+    };
+
+    // Map the key to its environment variable
+    let key = key.trim();
+    if key.contains('.') {
+        return Err(error!(r#"Unsupported dotted key "{key}"; settings must be in the global or a "[[relay]]" table"#));
+    }
+    let name = format!("WGPROXY_{}", key.to_uppercase().replace('-', "_"));
+    let is_bare = key.chars().all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_');
+    if key.is_empty() || !is_bare || name == "WGPROXY_CONFIG" || !cli::is_setting(&name) {
+        return Err(error!(r#"Unknown setting "{key}""#));
+    }
+
+    // Parse the value and ensure that only a comment may follow
+    let (value, rest) = parse_value(value.trim_start())?;
+    if !strip_comment(rest).trim().is_empty() {
+        return Err(error!(r#"Unexpected trailing characters "{}""#, rest.trim()));
+    }
+    Ok((name, value))
+}
+
+/// Parses a value or an array of values, and returns the raw value and the remaining input
+fn parse_value(input: &str) -> Result<(String, &str), Error> {
+    let Some(mut rest) = input.strip_prefix('[') else {
+        return parse_scalar(input);
+    };
+
+    // Join the array elements with commas
+    let mut elements = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix(']') {
+            return Ok((elements.join(","), rest));
+        }
+        let (element, tail) = parse_scalar(rest)?;
+        elements.push(element);
+
+        // Expect a separator or the end of the array
+        rest = tail.trim_start();
+        match rest.strip_prefix(',') {
+            Some(tail) => rest = tail,
+            None if rest.starts_with(']') => (),
+            None => return Err(error!("Unterminated array; arrays must be on a single line")),
+        }
+    }
+}
+
+/// Parses a string, an integer or a boolean, and returns the raw value and the remaining input
+fn parse_scalar(input: &str) -> Result<(String, &str), Error> {
+    // Reject the unsupported TOML values explicitly
+    if input.starts_with(r#"""""#) || input.starts_with("'''") {
+        return Err(error!("Unsupported multi-line string; strings must be on a single line"));
+    }
+    if input.starts_with('{') {
+        return Err(error!("Unsupported inline table; values must be strings, integers, booleans or arrays of them"));
+    }
+
+    // Literal strings are taken as-is
+    if let Some(rest) = input.strip_prefix('\'') {
+        let (value, rest) = rest.split_once('\'').ok_or_else(|| error!("Unterminated string"))?;
+        return Ok((value.to_string(), rest));
+    }
+    if let Some(rest) = input.strip_prefix('"') {
+        return parse_basic_string(rest);
+    }
+
+    // Integers and booleans end at the next delimiter
+    let end = input.find(|char: char| char.is_whitespace() || matches!(char, ',' | ']' | '#')).unwrap_or(input.len());
+    let (value, rest) = input.split_at(end);
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let is_integer = digits.starts_with(|char: char| char.is_ascii_digit())
+        && digits.chars().all(|char| char.is_ascii_digit() || char == '_');
+    match value {
+        "" => Err(error!("Missing value")),
+        "true" | "false" => Ok((value.to_string(), rest)),
+        _ if is_integer => Ok((value.replace(['_', '+'], ""), rest)),
+        _ => Err(error!(r#"Invalid value "{value}"; strings must be quoted"#)),
+    }
+}
+
+/// Parses a basic string after the opening quote, and returns the unescaped value and the remaining input
+fn parse_basic_string(input: &str) -> Result<(String, &str), Error> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, char)) = chars.next() {
+        let escaped = match char {
+            '"' => return Ok((value, input.get(index.saturating_add(1)..).unwrap_or_default())),
+            '\\' => chars.next().map(|(_, char)| char),
+            char => {
+                value.push(char);
+                continue;
+            }
+        };
+
+        // Unescape the character
+        let unescaped = match escaped {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some(escape @ ('u' | 'U')) => {
+                let digits = if escape == 'u' { 4 } else { 8 };
+                let hex: String = chars.by_ref().take(digits).map(|(_, char)| char).collect();
+                let codepoint = u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == digits);
+                codepoint
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error!(r#"Invalid unicode escape "\{escape}{hex}""#))?
+            }
+            Some(escape) => return Err(error!(r#"Invalid escape sequence "\{escape}""#)),
+            None => break,
+        };
+        value.push(unescaped);
+    }
+    Err(error!("Unterminated string"))
+}
+
+/// Strips a trailing comment from a line without strings
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(line, _)| line)
+}
@@ -0,0 +1,312 @@
+//! Zero-downtime socket handover between two relay processes
+//!
+//! # Protocol
+//! A new process connects to the handover socket of the running process. The running process stops its workers, and
+//! sends [`HEADER`] with the listening sockets attached as `SCM_RIGHTS`, followed by the serialized [`State`]. Once the
+//! state has been sent, the running process exits, and the new process continues with the same sockets and sessions.
+//!
+//! Both processes only trust a peer that runs as root or as their own user; the new process additionally trusts the
+//! unprivileged user that the running process has dropped to. Every read and write fails after [`TIMEOUT`], so that a
+//! stuck peer cannot block the takeover.
+
+use crate::error;
+use crate::error::Error;
+#[cfg(target_os = "linux")]
+use crate::log;
+#[cfg(target_os = "linux")]
+use crate::privileges;
+use crate::state::State;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::UdpSocket;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+/// The handover message header
+#[cfg(target_os = "linux")]
+const HEADER: &[u8] = b"wgproxy-handover 1\n";
+/// The maximum amount of sockets that can be handed over
+#[cfg(target_os = "linux")]
+const SOCKETS_MAX: usize = 64;
+/// The timeout for every read and write during the handover
+#[cfg(target_os = "linux")]
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pending handover request from a new relay process
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Request(UnixStream);
+
+/// A listener for handover requests from a new relay process
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Listener {
+    /// The underlying non-blocking listener
+    listener: UnixListener,
+}
+#[cfg(target_os = "linux")]
+impl Listener {
+    /// Binds the handover socket to the given path and replaces any stale socket file
+    pub fn bind(path: &Path) -> Result<Self, Error> {
+        // Remove the socket file of a previous process
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(error!(with: e, "Failed to remove stale handover socket {}", path.display()));
+            }
+            _ => (),
+        }
+
+        // Bind the listener
+        let listener = UnixListener::bind(path)
+            .map_err(|e| error!(with: e, "Failed to bind handover socket {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    /// Accepts a pending handover request, if any
+    ///
+    /// # Note
+    /// Requests from processes that run neither as root nor as the own user are rejected and ignored.
+    pub fn accept(&self) -> Result<Option<Request>, Error> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(error!(with: e, "Failed to accept handover request")),
+        };
+
+        // Only hand over to a trusted process
+        let uid = sys::peer_uid(&stream).map_err(|e| error!(with: e, "Failed to get handover peer credentials"))?;
+        if !is_trusted(uid, None) {
+            log!(warn: error!("Rejected handover request from untrusted user {uid}"));
+            return Ok(None);
+        }
+
+        // The stream must be blocking for the handover itself
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Some(Request(stream)))
+    }
+}
+
+/// Takes over the sockets and the state from a running relay process, or returns `None` if no process is listening
+///
+/// # Note
+/// The running process must run as root, as the own user or as the given user that it has dropped its privileges to.
+#[cfg(target_os = "linux")]
+pub fn receive(path: &Path, user: Option<&str>) -> Result<Option<(Vec<UdpSocket>, State)>, Error> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => return Ok(None),
+        Err(e) => return Err(error!(with: e, "Failed to connect to handover socket {}", path.display())),
+    };
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    // Only take over from a trusted process
+    let uid = sys::peer_uid(&stream).map_err(|e| error!(with: e, "Failed to get handover peer credentials"))?;
+    let user = user.map(privileges::user_ids).transpose()?.map(|(uid, _)| uid);
+    if !is_trusted(uid, user) {
+        return Err(error!("Refusing handover from previous process of untrusted user {uid}"));
+    }
+
+    // Receive the header with the sockets, and the serialized state
+    let mut header = [0; HEADER.len()];
+    let (header_len, sockets) = sys::recv_sockets(&stream, &mut header)
+        .map_err(|e| io_error(e, "Failed to receive sockets from previous process"))?;
+    let header_rest = header.get_mut(header_len..).unwrap_or_default();
+    stream.read_exact(header_rest).map_err(|e| io_error(e, "Failed to receive handover header"))?;
+    let (HEADER, false) = (header.as_slice(), sockets.is_empty()) else {
+        // The previous process is incompatible or has not sent any sockets
+        return Err(error!("Invalid handover from previous process"));
+    };
+    let mut state = String::new();
+    stream.read_to_string(&mut state).map_err(|e| io_error(e, "Failed to receive state from previous process"))?;
+
+    // Parse the state
+    let state = State::parse(&state).map_err(|e| error!("Invalid state from previous process: {e}"))?;
+    Ok(Some((sockets, state)))
+}
+
+/// Hands the sockets and the state over to a new relay process
+#[cfg(target_os = "linux")]
+pub fn send(request: Request, sockets: &[&UdpSocket], state: &State) -> Result<(), Error> {
+    let Request(mut stream) = request;
+    let header_len =
+        sys::send_sockets(&stream, HEADER, sockets).map_err(|e| io_error(e, "Failed to hand over sockets"))?;
+    let header_rest = HEADER.get(header_len..).unwrap_or_default();
+    stream.write_all(header_rest).map_err(|e| io_error(e, "Failed to send handover header"))?;
+    stream.write_all(state.to_string().as_bytes()).map_err(|e| io_error(e, "Failed to hand over state"))?;
+    Ok(())
+}
+
+/// Whether a peer with the given user id runs as root, as the own user or as the given user
+#[cfg(target_os = "linux")]
+fn is_trusted(uid: libc::uid_t, user: Option<libc::uid_t>) -> bool {
+    // Safety: `geteuid` has no preconditions and cannot fail
+    let euid = unsafe { libc::geteuid() };
+    uid == 0 || uid == euid || user == Some(uid)
+}
+
+/// Creates an error for a failed read or write with the given message, and reports an expired timeout as such
+#[cfg(target_os = "linux")]
+fn io_error(e: io::Error, message: &str) -> Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => error!(with: e, "{message}: Timed out after {TIMEOUT:?}"),
+        _ => error!(with: e, "{message}"),
+    }
+}
+
+/// `SCM_RIGHTS` message passing
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::SOCKETS_MAX;
+    use std::io;
+    use std::net::UdpSocket;
+    use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::{mem, ptr};
+
+    /// A control message buffer with the alignment of `cmsghdr`
+    struct Control(Vec<u64>);
+    impl Control {
+        /// Creates a control message buffer for the given amount of file descriptors
+        fn new(fds: usize) -> (Self, usize) {
+            // Safety: `CMSG_SPACE` is a pure computation
+            let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>().saturating_mul(fds) as u32) } as usize;
+            (Self(vec![0; space.div_ceil(mem::size_of::<u64>())]), space)
+        }
+    }
+
+    /// Sends the given data with the sockets attached, and returns the amount of bytes sent
+    pub fn send_sockets(stream: &UnixStream, data: &[u8], sockets: &[&UdpSocket]) -> io::Result<usize> {
+        let fds: Vec<RawFd> = sockets.iter().map(|socket| socket.as_raw_fd()).collect();
+        let fds_len = mem::size_of_val(fds.as_slice());
+        let (mut control, control_len) = Control::new(fds.len());
+
+        // Safety: `msghdr` is a plain C struct where all-zero is a valid bit pattern
+        let mut iovec = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        header.msg_control = control.0.as_mut_ptr().cast();
+        header.msg_controllen = control_len;
+
+        // Safety: The control buffer is aligned and large enough for a single message with all file descriptors
+        unsafe {
+            let message = libc::CMSG_FIRSTHDR(&header);
+            (*message).cmsg_level = libc::SOL_SOCKET;
+            (*message).cmsg_type = libc::SCM_RIGHTS;
+            (*message).cmsg_len = libc::CMSG_LEN(fds_len as u32) as usize;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(message).cast::<RawFd>(), fds.len());
+        }
+
+        // Safety: All pointers in the header are valid for the duration of the call
+        let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &header, libc::MSG_NOSIGNAL) };
+        usize::try_from(sent).map_err(|_| io::Error::last_os_error())
+    }
+
+    /// Gets the user id of the process at the other end of the stream
+    pub fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+        // Safety: `ucred` is a plain C struct where all-zero is a valid bit pattern
+        let mut credentials: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        // Safety: The credentials buffer is valid and `len` is its size
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&raw mut credentials).cast(),
+                &mut len,
+            )
+        };
+        match result {
+            0 => Ok(credentials.uid),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Receives data into the given buffer together with the attached sockets, and returns the amount of bytes received
+    pub fn recv_sockets(stream: &UnixStream, buf: &mut [u8]) -> io::Result<(usize, Vec<UdpSocket>)> {
+        let (mut control, control_len) = Control::new(SOCKETS_MAX);
+
+        // Safety: `msghdr` is a plain C struct where all-zero is a valid bit pattern
+        let mut iovec = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        header.msg_control = control.0.as_mut_ptr().cast();
+        header.msg_controllen = control_len;
+
+        // Safety: All pointers in the header are valid for the duration of the call
+        let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut header, libc::MSG_CMSG_CLOEXEC) };
+        let received = usize::try_from(received).map_err(|_| io::Error::last_os_error())?;
+
+        // Take ownership of all received file descriptors, so that they are closed on error
+        let mut sockets = Vec::new();
+        // Safety: The kernel has initialized the control messages within `msg_controllen`
+        let mut message = unsafe { libc::CMSG_FIRSTHDR(&header) };
+        while !message.is_null() {
+            // Safety: `message` points to a valid control message
+            let (level, kind, len) = unsafe { ((*message).cmsg_level, (*message).cmsg_type, (*message).cmsg_len) };
+            if level == libc::SOL_SOCKET && kind == libc::SCM_RIGHTS {
+                // Safety: `CMSG_LEN` is a pure computation
+                let data_len = len.saturating_sub(unsafe { libc::CMSG_LEN(0) } as usize);
+                let count = data_len.checked_div(mem::size_of::<RawFd>()).unwrap_or_default();
+                for index in 0..count {
+                    // Safety: The data contains `count` file descriptors which are now owned by us
+                    let fd = unsafe { ptr::read_unaligned(libc::CMSG_DATA(message).cast::<RawFd>().add(index)) };
+                    sockets.push(unsafe { UdpSocket::from_raw_fd(fd) });
+                }
+            }
+
+            // Safety: `message` is a valid control message within the header
+            message = unsafe { libc::CMSG_NXTHDR(&header, message) };
+        }
+
+        // Fail if some sockets have been discarded due to the limit
+        if header.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::other(format!("Too many sockets; at most {SOCKETS_MAX} are supported")));
+        }
+        Ok((received, sockets))
+    }
+}
+
+/// A pending handover request from a new relay process
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub enum Request {}
+
+/// A listener for handover requests from a new relay process
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct Listener;
+#[cfg(not(target_os = "linux"))]
+impl Listener {
+    /// Binds the handover socket to the given path and replaces any stale socket file
+    pub fn bind(_path: &Path) -> Result<Self, Error> {
+        Err(error!("Socket handover is only available on Linux"))
+    }
+
+    /// Accepts a pending handover request, if any
+    pub fn accept(&self) -> Result<Option<Request>, Error> {
+        Ok(None)
+    }
+}
+
+/// Takes over the sockets and the state from a running relay process, or returns `None` if no process is listening
+#[cfg(not(target_os = "linux"))]
+pub fn receive(_path: &Path, _user: Option<&str>) -> Result<Option<(Vec<UdpSocket>, State)>, Error> {
+    Err(error!("Socket handover is only available on Linux"))
+}
+
+/// Hands the sockets and the state over to a new relay process
+#[cfg(not(target_os = "linux"))]
+pub fn send(request: Request, _sockets: &[&UdpSocket], _state: &State) -> Result<(), Error> {
+    match request {}
+}
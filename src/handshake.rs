@@ -1,41 +1,15 @@
 //! Wireguard handshake validator
 
-use crate::error;
-use crate::error::Error;
+use crate::config::{Config, PublicKey};
+use crate::error::DropReason;
+use crate::replay::ReplayCache;
+use crate::{error, log};
 use blake2::digest::Mac;
 use blake2::digest::consts::U16;
 use blake2::digest::generic_array::GenericArray;
 use blake2::{Blake2s256, Blake2sMac, Digest};
-use std::collections::{HashSet, VecDeque};
-use std::hash::{BuildHasher, Hasher};
 use std::ops::Range;
-
-/// An identity hasher for valid aka evenly distributed MAC values
-#[derive(Debug, Clone, Copy)]
-struct MacHasher(u64);
-impl Hasher for MacHasher {
-    fn write(&mut self, bytes: &[u8]) {
-        for byte in bytes {
-            // Append byte to state
-            self.0 = (self.0 << 8) | (*byte as u64);
-        }
-    }
-
-    fn write_u64(&mut self, value: u64) {
-        self.0 = value;
-    }
-
-    fn finish(&self) -> u64 {
-        self.0
-    }
-}
-impl BuildHasher for MacHasher {
-    type Hasher = Self;
-
-    fn build_hasher(&self) -> Self::Hasher {
-        *self
-    }
-}
+use std::time::{Duration, SystemTime};
 
 /// A handshake validator
 ///
@@ -48,26 +22,42 @@ impl BuildHasher for MacHasher {
 /// See <https://www.wireguard.com/protocol/> for more information.
 #[derive(Debug)]
 pub struct Handshake {
-    /// The allowed public key for handshakes
-    public_key: [u8; 32],
-    /// A fast-lookup set of seen MACs
-    mac_index: HashSet<u64, MacHasher>,
-    /// An ordered history of seen MACs
-    mac_history: VecDeque<u64>,
+    /// The allowed public keys for handshakes
+    public_keys: Vec<PublicKey>,
+    /// The recently seen MACs
+    replay_cache: ReplayCache,
 }
 impl Handshake {
-    /// MAC history size (~4 MiB of storage)
-    const HISTORY_SIZE: usize = 1024 * 256;
-
     /// Creates a new handshake validator
-    pub fn new(public_key: [u8; 32]) -> Self {
-        let mac_index = HashSet::with_capacity_and_hasher(Self::HISTORY_SIZE, MacHasher(0));
-        let mac_history = VecDeque::with_capacity(Self::HISTORY_SIZE);
-        Self { public_key, mac_index, mac_history }
+    pub fn new(config: &Config) -> Self {
+        let replay_cache = ReplayCache::new(config.WGPROXY_REPLAY_WINDOW, config.WGPROXY_REPLAY_CAPACITY);
+        Self { public_keys: config.WGPROXY_PUBKEY.clone(), replay_cache }
+    }
+
+    /// Applies the public keys and replay cache limits of a reloaded config
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.public_keys = config.WGPROXY_PUBKEY.clone();
+        self.replay_cache.reconfigure(config.WGPROXY_REPLAY_WINDOW, config.WGPROXY_REPLAY_CAPACITY);
     }
 
-    /// Validates if a packet is a valid handshake initiation packet
-    pub fn is_valid_handshake(&mut self, packet: &[u8]) -> Result<(), Error> {
+    /// The recently seen MACs with their ages
+    pub fn snapshot(&self) -> impl Iterator<Item = (u128, Duration)> {
+        self.replay_cache.snapshot()
+    }
+
+    /// Restores recently seen MACs and discards stale ones
+    pub fn restore<I>(&mut self, macs: I)
+    where
+        I: IntoIterator<Item = (u128, Duration)>,
+    {
+        self.replay_cache.restore(macs);
+    }
+
+    /// Validates if a packet is a valid handshake initiation packet, and returns the matching public key
+    ///
+    /// # Key Rotation
+    /// Only public keys within their validity period are accepted, and expired public keys are dropped.
+    pub fn is_valid_handshake(&mut self, packet: &[u8]) -> Result<PublicKey, DropReason> {
         /// The exact length of a handshake initiation packet
         const PACKET_LENGTH: usize = 148;
         /// The offset/range of the message type field
@@ -84,57 +74,46 @@ impl Handshake {
         // Validate basic structure
         let PACKET_LENGTH = packet.len() else {
             // The packet has an invalid length
-            return Err(error!("Packet is not a handshake initiation packet"));
+            return Err(DropReason::MalformedHandshake);
         };
-        let MTYPE_VALUE = &packet[MTYPE_RANGE] else {
+        let Some(MTYPE_VALUE) = packet.get(MTYPE_RANGE) else {
             // The packet has an invalid message type/magic number
-            return Err(error!("Packet is not a handshake initiation packet"));
+            return Err(DropReason::MalformedHandshake);
+        };
+
+        let (Some(payload), Some(packet_mac1)) = (packet.get(PAYLOAD_RANGE), packet.get(MAC1_RANGE)) else {
+            // The packet is too short; this is already covered by the length check above
+            return Err(DropReason::MalformedHandshake);
         };
 
-        // Compute MAC1 over the packet
-        let label_pubkey_hash = Blake2s256::new().chain_update(MAC1_LABEL).chain_update(self.public_key).finalize();
-        let mac1 = Blake2sMac::<U16>::new(&label_pubkey_hash).chain_update(&packet[PAYLOAD_RANGE]);
+        // Drop the expired public keys
+        let now = SystemTime::now();
+        self.public_keys.retain(|public_key| {
+            let is_expired = public_key.is_expired_at(now);
+            if is_expired {
+                log!(info: error!("Public key {public_key} has expired and is no longer accepted"));
+            }
+            !is_expired
+        });
 
-        // See if the computed MAC1 matches the packet MAC1
-        let packet_mac1 = GenericArray::from_slice(&packet[MAC1_RANGE]);
-        let Ok(_) = mac1.verify(packet_mac1) else {
-            // MAC1 does not match our public key
-            return Err(error!("MAC1 does not match the server public key"));
+        // See if the MAC1 computed over the packet matches the packet MAC1 for any currently valid public key
+        let packet_mac1 = GenericArray::from_slice(packet_mac1);
+        let is_valid = |public_key: &&PublicKey| {
+            let label_pubkey_hash = Blake2s256::new().chain_update(MAC1_LABEL).chain_update(public_key.key).finalize();
+            let mac1 = Blake2sMac::<U16>::new(&label_pubkey_hash).chain_update(payload);
+            public_key.is_valid_at(now) && mac1.verify(packet_mac1).is_ok()
+        };
+        let Some(public_key) = self.public_keys.iter().find(is_valid).copied() else {
+            // MAC1 does not match any of our public keys
+            return Err(DropReason::InvalidMac1);
         };
 
         // MAC1 is valid, so check for previous occurrences and register it
         let packet_mac1 = <[u8; 16]>::from(*packet_mac1);
-        self.register_mac1(&packet_mac1)
-    }
-
-    /// Registers a new MAC with the history and returns `true` on success, or `false` if the MAC exists already
-    ///
-    /// # Collisions
-    /// For performance reasons, the registry only stores the middle 64 bit of the full 128 bit hash. In theory, this
-    /// could cause some collisions over time; however in practice this should not happen too often. If a collision
-    /// occurs, the client will simply send a new handshake with a new MAC.
-    // Note: This function uses a boolean return value to avoid the more expensive error creation on failure
-    #[must_use]
-    fn register_mac1(&mut self, mac: &[u8; 16]) -> Result<(), Error> {
-        // See if the shortened MAC exists already
-        let mac64 = u64::from_ne_bytes([mac[4], mac[5], mac[6], mac[7], mac[8], mac[9], mac[10], mac[11]]);
-        let false = self.mac_index.contains(&mac64) else {
+        let true = self.replay_cache.insert(u128::from_be_bytes(packet_mac1)) else {
             // MAC has already been seen before
-            let mac = u128::from_be_bytes(*mac);
-            return Err(error!("MAC1 {mac:032x} has already been seen before"));
+            return Err(DropReason::ReplayedMac1(packet_mac1));
         };
-
-        // Evict old MAC if necessary
-        if self.mac_history.len() == Self::HISTORY_SIZE
-            && let Some(to_evict) = self.mac_history.pop_front()
-        {
-            // Remove oldest value from index
-            self.mac_index.remove(&to_evict);
-        }
-
-        // Insert new value
-        self.mac_index.insert(mac64);
-        self.mac_history.push_back(mac64);
-        Ok(())
+        Ok(public_key)
     }
 }
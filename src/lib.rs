@@ -12,72 +12,351 @@
 #![warn(clippy::allow_attributes_without_reason)]
 #![warn(clippy::cognitive_complexity)]
 
+mod backend;
+pub mod cli;
 pub mod config;
 pub mod error;
+mod file;
+mod handover;
 mod handshake;
+mod packet;
+mod privileges;
+mod relay;
+mod replay;
+mod sandbox;
 mod session;
+pub mod signal;
+mod state;
+mod systemd;
+mod table;
+pub mod wgquick;
 
+use crate::backend::{Heartbeat, WAKEUP_INTERVAL};
 use crate::config::Config;
 use crate::error::Error;
-use crate::handshake::Handshake;
-use crate::session::Session;
-use std::cell::Cell;
-use std::convert::Infallible;
+use crate::relay::Relay;
+use crate::state::State;
+use crate::systemd::Notifier;
 use std::net::UdpSocket;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-thread_local! {
-    /// Thread-global log level to allow context-free logging
-    pub(crate) static LOGLEVEL: Cell<u8> = Cell::new(1);
+/// Process-global log level to allow context-free logging from any thread
+pub(crate) static LOGLEVEL: AtomicU8 = AtomicU8::new(1);
+
+/// A relay with its listening sockets
+struct Instance {
+    /// The relay config
+    config: Config,
+    /// The listening sockets
+    sockets: Vec<Arc<UdpSocket>>,
+    /// The shared relay state
+    relay: Arc<Mutex<Relay>>,
 }
 
 /// The packet-forwarding event loop
-pub fn eventloop(config: Config) -> Result<Infallible, Error> {
-    // Set log-level from config
-    LOGLEVEL.set(config.WGPROXY_LOGLEVEL);
-    log!(info: &config);
-
-    // Setup relay state
-    let socket = UdpSocket::bind(config.WGPROXY_LISTEN)?;
-    let mut validator = Handshake::new(config.WGPROXY_PUBKEY);
-    let mut session: Option<Session> = None;
-
-    // Start network loop
-    let mut buf = [0; 4096];
-    'network_loop: loop {
-        // Receive next inbound packet
-        let (buf_len, source_addr) =
-            socket.recv_from(&mut buf).map_err(|e| error!(with: e, "Failed to receive inbound packet"))?;
-        let packet = &buf[..buf_len];
-
-        // Check for session timeouts
-        if let Some(session_) = session.as_ref()
-            && session_.atime().elapsed() > config.WGPROXY_TIMEOUT
-        {
-            // Drop session
-            log!(info: error!("Dropping expired session {session_}"));
-            session = None;
+///
+/// # Shutdown
+/// The event loop runs until a fatal error occurs or a graceful shutdown is requested via [`signal::terminate`] or a
+/// termination signal. In the latter case, the relay drains for up to [`Config::WGPROXY_DRAIN`] and returns `Ok(())`.
+///
+/// # Reload
+/// If a reload is requested via [`signal::reload`] or `SIGHUP`, the config is re-read from the environment and applied
+/// to the running relay without interrupting existing sessions.
+///
+/// # Handover
+/// If [`Config::WGPROXY_HANDOVER`] is set, the event loop takes over from a running relay process on startup, and hands
+/// itself over to the next relay process on request. After a handover, the event loop returns `Ok(())`.
+pub fn eventloop(config: Config) -> Result<(), Error> {
+    run(vec![config], || Ok(vec![Config::from_env()?]))
+}
+
+/// The packet-forwarding event loop for all relays defined in the given config file
+///
+/// # Process-Wide Settings
+/// All relays run in the current process and share the log level, the drain period, the privilege and the sandbox
+/// settings. Apart from that, this behaves like [`eventloop`], except that the config file is re-read on reload.
+pub fn eventloop_file(path: &Path) -> Result<(), Error> {
+    let configs = Config::from_file(path)?;
+    run(configs, || Config::from_file(path))
+}
+
+/// Runs the given relays until a fatal error occurs, a graceful shutdown or a handover is requested
+fn run<F>(configs: Vec<Config>, mut load: F) -> Result<(), Error>
+where
+    F: FnMut() -> Result<Vec<Config>, Error>,
+{
+    // The process-wide settings are the same for all relays
+    let Some(process) = configs.first().cloned() else {
+        return Err(error!("No relays configured"));
+    };
+    LOGLEVEL.store(process.WGPROXY_LOGLEVEL, Ordering::Relaxed);
+
+    // Setup the shared relay states and the handover listener
+    let single = configs.len() == 1;
+    let mut instances = Vec::new();
+    for config in configs {
+        log!(info: &config);
+        let (sockets, relay) = setup(&config, single)?;
+        let sockets = sockets.into_iter().map(Arc::new).collect();
+        instances.push(Instance { config, sockets, relay: Arc::new(Mutex::new(relay)) });
+    }
+    let listener = process.WGPROXY_HANDOVER.as_deref().map(handover::Listener::bind).transpose()?;
+
+    // Drop privileges and enter the sandbox before any packet is processed
+    privileges::drop(&process)?;
+    let configs: Vec<Config> = instances.iter().map(|instance| instance.config.clone()).collect();
+    sandbox::enter(&configs)?;
+
+    // Spawn one worker per socket and one state snapshot thread per relay; threads only return early on fatal errors
+    let stop = Arc::new(AtomicBool::new(false));
+    let (error_tx, error_rx) = mpsc::channel();
+    let mut threads = Vec::new();
+    let mut heartbeats = Vec::new();
+    for Instance { config, sockets, relay } in &instances {
+        for socket in sockets {
+            let heartbeat = Arc::new(Heartbeat::new());
+            heartbeats.push(heartbeat.clone());
+            let (socket, config, relay, stop, error_tx) =
+                (socket.clone(), config.clone(), relay.clone(), stop.clone(), error_tx.clone());
+            threads.push(thread::spawn(move || {
+                if let Err(e) = backend::run(&socket, &config, &relay, (&stop, &heartbeat)) {
+                    let _ = error_tx.send(e);
+                }
+            }));
+        }
+        if let Some(path) = config.WGPROXY_STATE.clone() {
+            let (interval, relay, stop, error_tx) =
+                (config.WGPROXY_STATE_INTERVAL, relay.clone(), stop.clone(), error_tx.clone());
+            threads.push(thread::spawn(move || {
+                if let Err(e) = state::snapshot_loop(&path, interval, &relay, &stop) {
+                    let _ = error_tx.send(e);
+                }
+            }));
+        }
+    }
+
+    // Wait for a fatal error, a termination or a handover request, and handle reload requests in the meantime
+    drop(error_tx);
+    let mut notifier = Notifier::from_env()?;
+    notifier.notify("READY=1");
+    let (mut request, mut resolved) = (None, Instant::now());
+    wait(&error_rx, || {
+        notifier.tick(session_count(&instances)?, heartbeat_age(&heartbeats));
+        if signal::take_reload() {
+            reload(&mut instances, &mut load)?;
         }
+        if resolved.elapsed() >= Relay::RESOLVE_INTERVAL {
+            resolve(&instances)?;
+            resolved = Instant::now();
+        }
+        if let Some(listener) = &listener {
+            request = listener.accept()?;
+        }
+        Ok(signal::is_terminated() || request.is_some())
+    })?;
 
-        // Start a new session if there is no current session and the packet is a handshake packet
-        if session.is_none()
-            && let Ok(_) = log!(debug: validator.is_valid_handshake(packet))
-        {
-            // If we cannot create a new session, this is probably fatal
-            let session_ = Session::new(&source_addr, &config, &socket)?;
-            session = Some(session_);
+    // Hand the relay over to the new process; handover is only available for a single relay
+    notifier.notify("STOPPING=1");
+    if let (Some(request), [instance]) = (request, instances.as_slice()) {
+        stop.store(true, Ordering::Relaxed);
+        for thread in threads {
+            let _ = thread.join();
         }
+        let sockets: Vec<&UdpSocket> = instance.sockets.iter().map(Arc::as_ref).collect();
+        handover::send(request, &sockets, &Relay::lock(&instance.relay)?.snapshot())?;
+        log!(info: error!("Handed over to new relay process"));
+        return Ok(());
+    }
 
-        // Unpack current session or log info
-        let Some(session) = session.as_mut() else {
-            // This is not an error as rogue packets may arrive anytime
-            log!(debug: error!("Cannot forward packet without valid session"));
-            continue 'network_loop;
-        };
+    // Drain the relays until all sessions have expired or the possibly reloaded drain period has elapsed
+    let drain = instances.first().map_or(process.WGPROXY_DRAIN, |instance| instance.config.WGPROXY_DRAIN);
+    log!(info: error!("Draining sessions for {drain:?}"));
+    for instance in &instances {
+        Relay::lock(&instance.relay)?.drain();
+    }
+    let draining = Instant::now();
+    wait(&error_rx, || {
+        let sessions = session_count(&instances)?;
+        notifier.tick(sessions, heartbeat_age(&heartbeats));
+        Ok(draining.elapsed() >= drain || sessions == 0)
+    })?;
+
+    // Stop all threads and log the final state
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        let _ = thread.join();
+    }
+    for instance in &instances {
+        shutdown(&instance.config, &instance.relay)?;
+    }
+    log!(info: error!("Shutdown complete"));
+    Ok(())
+}
+
+/// Takes over the sockets and the state from a running relay process, or uses the sockets passed by systemd or binds
+/// new sockets and loads the state file
+///
+/// # Note
+/// If there are multiple relays, socket activation is not available as the sockets cannot be assigned to the relays.
+fn setup(config: &Config, single: bool) -> Result<(Vec<UdpSocket>, Relay), Error> {
+    // Try to take over from a running process first
+    let takeover = match &config.WGPROXY_HANDOVER {
+        Some(path) => handover::receive(path, config.WGPROXY_USER.as_deref())?,
+        None => None,
+    };
+    let (sockets, state) = match takeover {
+        Some((sockets, state)) => {
+            log!(info: error!("Took over {} sockets from previous relay process", sockets.len()));
+            (sockets, Some(state))
+        }
+        None => {
+            let sockets = match systemd::listen_fds()? {
+                Some(sockets) if single => {
+                    log!(info: error!("Using {} sockets passed by systemd", sockets.len()));
+                    sockets
+                }
+                Some(_) => return Err(error!("Socket activation is only available for a single relay")),
+                None => backend::bind(config)?,
+            };
+            let state = config.WGPROXY_STATE.as_deref().and_then(|path| log!(warn: State::load(path)).ok().flatten());
+            (sockets, state)
+        }
+    };
+
+    // Restore the previous state
+    let mut relay = Relay::new(config.clone(), Relay::resolve(config)?);
+    if let Some(state) = state {
+        relay.restore(&state);
+    }
+    Ok((sockets, relay))
+}
+
+/// The total amount of active sessions of all relays
+fn session_count(instances: &[Instance]) -> Result<usize, Error> {
+    let mut sessions = 0usize;
+    for instance in instances {
+        sessions = sessions.saturating_add(Relay::lock(&instance.relay)?.session_count());
+    }
+    Ok(sessions)
+}
+
+/// The time since the least recent heartbeat of all workers
+fn heartbeat_age(heartbeats: &[Arc<Heartbeat>]) -> Duration {
+    heartbeats.iter().map(|heartbeat| heartbeat.age()).max().unwrap_or_default()
+}
+
+/// Waits until `done` returns `true` or a thread reports a fatal error
+fn wait<F>(error_rx: &Receiver<Error>, mut done: F) -> Result<(), Error>
+where
+    F: FnMut() -> Result<bool, Error>,
+{
+    loop {
+        match error_rx.recv_timeout(WAKEUP_INTERVAL) {
+            Ok(error) => return Err(error),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(e @ RecvTimeoutError::Disconnected) => return Err(error!(with: e, "All workers have stopped")),
+        }
+        if done()? {
+            return Ok(());
+        }
+    }
+}
+
+/// Re-reads the configs and applies them to the running relays
+fn reload<F>(instances: &mut [Instance], load: &mut F) -> Result<(), Error>
+where
+    F: FnMut() -> Result<Vec<Config>, Error>,
+{
+    // An invalid config must not take down the running relays
+    log!(info: error!("Reloading config"));
+    let Ok(reloaded) = log!(warn: load()) else {
+        return Ok(());
+    };
+    if reloaded.len() != instances.len() {
+        log!(warn: error!("Changing the amount of relays requires a restart; keeping the current config"));
+        return Ok(());
+    }
 
-        // Forward the packet
-        let Ok(()) = log!(warn: session.forward(packet, &source_addr)) else {
-            // This is not necessarily fatal, but worth a warning
-            continue 'network_loop;
+    // Apply the configs to their relays
+    for (instance, reloaded) in instances.iter_mut().zip(reloaded) {
+        reload_relay(&mut instance.config, reloaded, &instance.relay)?;
+    }
+    if let Some(instance) = instances.first() {
+        LOGLEVEL.store(instance.config.WGPROXY_LOGLEVEL, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Re-resolves the server addresses of the running relays
+fn resolve(instances: &[Instance]) -> Result<(), Error> {
+    for instance in instances {
+        // A temporary resolution failure must not take down the running relays; resolve before taking the relay lock
+        let Ok(server) = log!(warn: Relay::resolve(&instance.config)) else {
+            continue;
         };
+        Relay::lock(&instance.relay)?.update_server(server);
+    }
+    Ok(())
+}
+
+/// Applies a reloaded config to a running relay
+fn reload_relay(config: &mut Config, reloaded: Config, relay: &Mutex<Relay>) -> Result<(), Error> {
+    // The sockets and threads cannot be changed at runtime
+    let restart_required = [
+        ("WGPROXY_LISTEN", reloaded.WGPROXY_LISTEN != config.WGPROXY_LISTEN),
+        ("WGPROXY_BACKEND", reloaded.WGPROXY_BACKEND != config.WGPROXY_BACKEND),
+        ("WGPROXY_BATCH_SIZE", reloaded.WGPROXY_BATCH_SIZE != config.WGPROXY_BATCH_SIZE),
+        ("WGPROXY_OFFLOAD", reloaded.WGPROXY_OFFLOAD != config.WGPROXY_OFFLOAD),
+        ("WGPROXY_WORKERS", reloaded.WGPROXY_WORKERS != config.WGPROXY_WORKERS),
+        ("WGPROXY_STATE", reloaded.WGPROXY_STATE != config.WGPROXY_STATE),
+        ("WGPROXY_STATE_INTERVAL", reloaded.WGPROXY_STATE_INTERVAL != config.WGPROXY_STATE_INTERVAL),
+        ("WGPROXY_HANDOVER", reloaded.WGPROXY_HANDOVER != config.WGPROXY_HANDOVER),
+        ("WGPROXY_USER", reloaded.WGPROXY_USER != config.WGPROXY_USER),
+        ("WGPROXY_GROUP", reloaded.WGPROXY_GROUP != config.WGPROXY_GROUP),
+        ("WGPROXY_SANDBOX", reloaded.WGPROXY_SANDBOX != config.WGPROXY_SANDBOX),
+    ];
+    for (name, _) in restart_required.iter().filter(|(_, changed)| *changed) {
+        log!(warn: error!("Changing {name} requires a restart; keeping the current value"));
+    }
+    let reloaded = Config {
+        WGPROXY_LISTEN: config.WGPROXY_LISTEN,
+        WGPROXY_BACKEND: config.WGPROXY_BACKEND,
+        WGPROXY_BATCH_SIZE: config.WGPROXY_BATCH_SIZE,
+        WGPROXY_OFFLOAD: config.WGPROXY_OFFLOAD,
+        WGPROXY_WORKERS: config.WGPROXY_WORKERS,
+        WGPROXY_STATE: config.WGPROXY_STATE.clone(),
+        WGPROXY_STATE_INTERVAL: config.WGPROXY_STATE_INTERVAL,
+        WGPROXY_HANDOVER: config.WGPROXY_HANDOVER.clone(),
+        WGPROXY_USER: config.WGPROXY_USER.clone(),
+        WGPROXY_GROUP: config.WGPROXY_GROUP.clone(),
+        WGPROXY_SANDBOX: config.WGPROXY_SANDBOX,
+        ..reloaded
+    };
+
+    // Resolve the server address before taking the relay lock, as the resolution may block
+    let Ok(server) = log!(warn: Relay::resolve(&reloaded)) else {
+        return Ok(());
+    };
+
+    // Apply the remaining settings
+    Relay::lock(relay)?.reconfigure(reloaded.clone(), server);
+    log!(info: &reloaded);
+    *config = reloaded;
+    Ok(())
+}
+
+/// Logs the final session statistics and writes the final state snapshot
+fn shutdown(config: &Config, relay: &Mutex<Relay>) -> Result<(), Error> {
+    let relay = Relay::lock(relay)?;
+    relay.shutdown();
+    if let Some(path) = &config.WGPROXY_STATE {
+        // This is not necessarily fatal, but worth a warning
+        let _ = log!(warn: relay.snapshot().save(path));
     }
+    Ok(())
 }
@@ -12,14 +12,75 @@
 #![warn(clippy::allow_attributes_without_reason)]
 #![warn(clippy::cognitive_complexity)]
 
-use std::process;
+use std::collections::BTreeMap;
+use std::{env, fs, process};
+use wgproxy::cli::{self, Args, Command};
 use wgproxy::config::Config;
+use wgproxy::error::Error;
+use wgproxy::wgquick;
 
 pub fn main() {
-    // Load config and enter app runloop
-    let Err(e) = Config::from_env().and_then(wgproxy::eventloop);
+    // Parse the command line and execute the command
+    let result = Args::parse(env::args().skip(1)).and_then(run);
+    let Err(e) = result else {
+        // Graceful shutdown
+        process::exit(0);
+    };
     wgproxy::log!(fatal: e);
 
     // Exit with error status
     process::exit(1);
 }
+
+/// Executes the given command
+fn run(args: Args) -> Result<(), Error> {
+    match &args.command {
+        Command::Help => print!("{}", cli::help()),
+        Command::Version => println!("{}", cli::version()),
+        Command::Check => {
+            // Apply the flags and validate the config without starting the relay
+            args.apply()?;
+            check()?;
+        }
+        Command::RewriteEndpoint { config, endpoint } => {
+            // Print the rewritten config so that it can be reviewed or redirected
+            let text = fs::read_to_string(config)
+                .map_err(|e| wgproxy::error!(with: e, "Failed to read WireGuard config {}", config.display()))?;
+            let rewritten = wgquick::rewrite_endpoint(&text, endpoint)
+                .map_err(|e| wgproxy::error!("Invalid WireGuard config {}: {e}", config.display()))?;
+            print!("{rewritten}");
+        }
+        Command::Run => {
+            // Apply the flags, install signal handlers, load config and enter app runloop
+            args.apply()?;
+            wgproxy::signal::install()?;
+            match Config::wgproxy_config()? {
+                Some(path) => wgproxy::eventloop_file(&path)?,
+                None => wgproxy::eventloop(Config::from_env()?)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validates the config from the config file or the environment, prints it and reports all problems
+fn check() -> Result<(), Error> {
+    let checked = match Config::wgproxy_config()? {
+        Some(path) => Config::check_file(&path),
+        None => Config::check(&BTreeMap::new()).map(|config| vec![config]),
+    };
+    match checked {
+        Ok(configs) => {
+            for config in configs {
+                println!("{config}");
+            }
+            Ok(())
+        }
+        Err(problems) => {
+            for problem in &problems {
+                eprintln!("[FAIL] {problem}");
+            }
+            Err(wgproxy::error!("The config has {} problems", problems.len()))
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! WireGuard message classification
+
+/// A WireGuard message type
+///
+/// See <https://www.wireguard.com/protocol/> for more information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A handshake initiation message
+    Initiation,
+    /// A handshake response message
+    Response,
+    /// A cookie reply message
+    CookieReply,
+    /// A transport data message
+    Transport,
+}
+impl MessageType {
+    /// Classifies the given packet by its message type field, or returns `None` if the type is unknown
+    pub fn of(packet: &[u8]) -> Option<Self> {
+        match packet.get(0..4)? {
+            b"\x01\x00\x00\x00" => Some(Self::Initiation),
+            b"\x02\x00\x00\x00" => Some(Self::Response),
+            b"\x03\x00\x00\x00" => Some(Self::CookieReply),
+            b"\x04\x00\x00\x00" => Some(Self::Transport),
+            _ => None,
+        }
+    }
+}
+
+/// Gets the sender index of a handshake initiation or response packet
+pub fn sender_index(packet: &[u8]) -> Option<u32> {
+    match MessageType::of(packet)? {
+        MessageType::Initiation | MessageType::Response => index_at(packet, 4),
+        MessageType::CookieReply | MessageType::Transport => None,
+    }
+}
+
+/// Gets the receiver index of a handshake response, cookie reply or transport data packet
+pub fn receiver_index(packet: &[u8]) -> Option<u32> {
+    match MessageType::of(packet)? {
+        MessageType::Response => index_at(packet, 8),
+        MessageType::CookieReply | MessageType::Transport => index_at(packet, 4),
+        MessageType::Initiation => None,
+    }
+}
+
+/// Reads a little-endian session index at the given offset
+fn index_at(packet: &[u8], offset: usize) -> Option<u32> {
+    let bytes = packet.get(offset..offset.checked_add(4)?)?;
+    let bytes = <[u8; 4]>::try_from(bytes).ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
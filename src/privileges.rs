@@ -0,0 +1,190 @@
+//! Privilege dropping after the sockets have been bound
+
+use crate::config::Config;
+use crate::error;
+use crate::error::Error;
+#[cfg(target_os = "linux")]
+use crate::log;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::{io, mem, ptr};
+
+/// Switches to the configured user and group, clears all supplementary groups and capabilities, and fails if any step
+/// does not succeed
+#[cfg(target_os = "linux")]
+pub fn drop(config: &Config) -> Result<(), Error> {
+    // Resolve the target ids; the group defaults to the primary group of the user
+    let (uid, primary_gid) = match &config.WGPROXY_USER {
+        Some(user) => {
+            let (uid, gid) = user_ids(user)?;
+            if gid.is_none() && config.WGPROXY_GROUP.is_none() {
+                // We must not guess the group of a user without passwd entry
+                return Err(error!(r#"User "{user}" has no passwd entry, so WGPROXY_GROUP must be set"#));
+            }
+            (Some(uid), gid)
+        }
+        None => (None, None),
+    };
+    let gid = match &config.WGPROXY_GROUP {
+        Some(group) => Some(group_id(group)?),
+        None => primary_gid,
+    };
+    if uid.is_none() && gid.is_none() {
+        // Nothing to drop
+        return Ok(());
+    }
+
+    // Drop the capability bounding set while we still have `CAP_SETPCAP`
+    for capability in 0.. {
+        // Safety: `prctl` with `PR_CAPBSET_DROP` has no memory safety requirements
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, capability, 0, 0, 0) } != 0 {
+            match io::Error::last_os_error().raw_os_error() {
+                // The capability does not exist, so we have dropped all capabilities
+                Some(libc::EINVAL) => break,
+                _ => return Err(error!(with: io::Error::last_os_error(), "Failed to drop capability {capability}")),
+            }
+        }
+    }
+
+    // Switch the groups before the user, as we need the privileges to do so
+    // Safety: `setgroups` with an empty list has no memory safety requirements
+    if unsafe { libc::setgroups(0, ptr::null()) } != 0 {
+        return Err(error!(with: io::Error::last_os_error(), "Failed to clear supplementary groups"));
+    }
+    // Safety: `setgid`/`setuid` have no memory safety requirements; the libc applies them to all threads
+    if let Some(gid) = gid
+        && unsafe { libc::setgid(gid) } != 0
+    {
+        return Err(error!(with: io::Error::last_os_error(), "Failed to switch to group {gid}"));
+    }
+    // Safety: See above
+    if let Some(uid) = uid
+        && unsafe { libc::setuid(uid) } != 0
+    {
+        return Err(error!(with: io::Error::last_os_error(), "Failed to switch to user {uid}"));
+    }
+
+    // Clear the remaining capabilities
+    clear_capabilities()?;
+    verify(uid, gid)?;
+    log!(info: error!("Dropped privileges to user {uid:?} and group {gid:?}"));
+    Ok(())
+}
+
+/// Switches to the configured user and group, clears all supplementary groups and capabilities, and fails if any step
+/// does not succeed
+#[cfg(not(target_os = "linux"))]
+pub fn drop(config: &Config) -> Result<(), Error> {
+    match (&config.WGPROXY_USER, &config.WGPROXY_GROUP) {
+        (None, None) => Ok(()),
+        _ => Err(error!("Privilege dropping is only available on Linux")),
+    }
+}
+
+/// Resolves a user name or numeric id to its user and primary group id, if the user has a passwd entry
+#[cfg(target_os = "linux")]
+pub fn user_ids(user: &str) -> Result<(libc::uid_t, Option<libc::gid_t>), Error> {
+    let name = CString::new(user).map_err(|e| error!(with: e, r#"Invalid user "{user}""#))?;
+    let mut buf = vec![0; 16384];
+    // Safety: `passwd` is a plain C struct where all-zero is a valid bit pattern
+    let mut entry: libc::passwd = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+
+    // Safety: All pointers are valid for the duration of the call, and the buffer length is correct
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if status == 0 && !result.is_null() {
+        return Ok((entry.pw_uid, Some(entry.pw_gid)));
+    }
+
+    // Fall back to a numeric id, and look up its primary group
+    let uid = user.parse::<libc::uid_t>().map_err(|_| error!(r#"Unknown user "{user}""#))?;
+    // Safety: All pointers are valid for the duration of the call, and the buffer length is correct
+    let status = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+    match status == 0 && !result.is_null() {
+        true => Ok((uid, Some(entry.pw_gid))),
+        false => Ok((uid, None)),
+    }
+}
+
+/// Resolves a group name or numeric id to its group id
+#[cfg(target_os = "linux")]
+fn group_id(group: &str) -> Result<libc::gid_t, Error> {
+    let name = CString::new(group).map_err(|e| error!(with: e, r#"Invalid group "{group}""#))?;
+    let mut buf = vec![0; 16384];
+    // Safety: `group` is a plain C struct where all-zero is a valid bit pattern
+    let mut entry: libc::group = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+
+    // Safety: All pointers are valid for the duration of the call, and the buffer length is correct
+    let status = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if status == 0 && !result.is_null() {
+        return Ok(entry.gr_gid);
+    }
+
+    // Fall back to a numeric id
+    group.parse::<libc::gid_t>().map_err(|_| error!(r#"Unknown group "{group}""#))
+}
+
+/// Clears the ambient, permitted, effective and inheritable capabilities of the current thread
+///
+/// # Note
+/// This must be called before any other thread is spawned, as capabilities are a per-thread attribute.
+#[cfg(target_os = "linux")]
+fn clear_capabilities() -> Result<(), Error> {
+    /// The capability header as expected by `capset`
+    #[repr(C)]
+    struct Header {
+        /// The capability ABI version
+        version: u32,
+        /// The target thread or `0` for the current thread
+        pid: libc::c_int,
+    }
+    /// A capability set as expected by `capset`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Data {
+        /// The effective capabilities
+        effective: u32,
+        /// The permitted capabilities
+        permitted: u32,
+        /// The inheritable capabilities
+        inheritable: u32,
+    }
+    /// The 64 bit capability ABI version
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    // Clear the ambient capabilities; this fails on kernels without ambient capabilities, where there is nothing to do
+    // Safety: `prctl` with `PR_CAP_AMBIENT` has no memory safety requirements
+    unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) };
+
+    // Clear all other capabilities
+    let header = Header { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let data = [Data::default(); 2];
+    // Safety: The header and data have the layout expected by the kernel for the given ABI version
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(error!(with: io::Error::last_os_error(), "Failed to clear capabilities"));
+    }
+    Ok(())
+}
+
+/// Verifies that the privileges have been dropped and cannot be regained
+#[cfg(target_os = "linux")]
+fn verify(uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> Result<(), Error> {
+    // Safety: These functions have no memory safety requirements and always succeed
+    let (real_uid, effective_uid, real_gid, effective_gid) =
+        unsafe { (libc::getuid(), libc::geteuid(), libc::getgid(), libc::getegid()) };
+    if uid.is_some_and(|uid| real_uid != uid || effective_uid != uid) {
+        return Err(error!("Failed to switch user; still running as {effective_uid}"));
+    }
+    if gid.is_some_and(|gid| real_gid != gid || effective_gid != gid) {
+        return Err(error!("Failed to switch group; still running as {effective_gid}"));
+    }
+
+    // We must not be able to become root again
+    // Safety: `setuid` has no memory safety requirements
+    if uid.is_some_and(|uid| uid != 0) && unsafe { libc::setuid(0) } == 0 {
+        return Err(error!("Privileges can be regained after dropping them"));
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! The I/O-agnostic relay state machine
+
+use crate::config::Config;
+use crate::error;
+use crate::error::{DropReason, Error};
+use crate::handshake::Handshake;
+use crate::log;
+use crate::packet::MessageType;
+use crate::session::Session;
+use crate::state::State;
+use crate::table::{Admission, SessionTable};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// The relay state
+///
+/// # I/O
+/// The relay does not perform any I/O itself; it only decides where an incoming packet should be forwarded to. This
+/// allows the different I/O backends to receive and send packets in whatever way is most efficient for them.
+#[derive(Debug)]
+pub struct Relay {
+    /// The relay config
+    config: Config,
+    /// The resolved server address for new sessions
+    server: SocketAddr,
+    /// The handshake validator
+    validator: Handshake,
+    /// The active sessions
+    sessions: SessionTable,
+    /// Whether the relay is draining, i.e. does not accept new sessions anymore
+    draining: bool,
+}
+impl Relay {
+    /// The interval to re-resolve the server address to follow e.g. dynDNS or load balancing changes
+    pub const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Creates a new relay with the server address resolved via [`Self::resolve`]
+    pub fn new(config: Config, server: SocketAddr) -> Self {
+        let validator = Handshake::new(&config);
+        let sessions = SessionTable::new(&config);
+        Self { config, server, validator, sessions, draining: false }
+    }
+
+    /// Resolves the server address of the given config
+    ///
+    /// # Note
+    /// The resolution may block on DNS, so it must not be performed while holding the relay lock.
+    pub fn resolve(config: &Config) -> Result<SocketAddr, Error> {
+        let server = &config.WGPROXY_SERVER;
+        let mut addresses =
+            server.to_socket_addrs().map_err(|e| error!(with: e, "Failed to resolve server address {server}"))?;
+        addresses.next().ok_or_else(|| error!("Failed to resolve server address {server}"))
+    }
+
+    /// Locks a shared relay state
+    pub fn lock(relay: &Mutex<Self>) -> Result<MutexGuard<'_, Self>, Error> {
+        // A poisoned lock means that another thread has panicked while holding the relay state
+        relay.lock().map_err(|_| error!("Relay state is poisoned"))
+    }
+
+    /// Captures the persistent relay state
+    pub fn snapshot(&self) -> State {
+        let macs = self.validator.snapshot().collect();
+        let sessions = self.sessions.sessions().map(Session::snapshot).collect();
+        State { macs, sessions }
+    }
+
+    /// Restores a persisted relay state and discards stale entries
+    pub fn restore(&mut self, state: &State) {
+        self.validator.restore(state.macs.iter().copied());
+        for session in &state.sessions {
+            // Restore the session if it is not stale and there is a free slot
+            let Some(session) = Session::restore(session, &self.server, &self.config) else {
+                continue;
+            };
+            let Ok(Admission::Free) = self.sessions.admit(session.client_address()) else {
+                continue;
+            };
+            log!(info: error!("Restored session {session}"));
+            self.sessions.insert(session, Admission::Free);
+        }
+    }
+
+    /// Applies a reloaded config and its resolved server address; existing sessions are kept, but new sessions use the
+    /// new server address
+    pub fn reconfigure(&mut self, config: Config, server: SocketAddr) {
+        self.validator.reconfigure(&config);
+        self.sessions.reconfigure(&config);
+        self.config = config;
+        self.update_server(server);
+    }
+
+    /// Applies a re-resolved server address; existing sessions are kept, but new sessions use the new server address
+    pub fn update_server(&mut self, server: SocketAddr) {
+        if server != self.server {
+            log!(info: error!("Server address changed from {} to {server}", self.server));
+            self.server = server;
+        }
+    }
+
+    /// Stops accepting new sessions, while existing sessions are still forwarded
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    /// The amount of active sessions that have not expired yet
+    pub fn session_count(&mut self) -> usize {
+        self.sessions.expire();
+        self.sessions.len()
+    }
+
+    /// Logs the final statistics of all remaining sessions
+    pub fn shutdown(&self) {
+        for session in self.sessions.sessions() {
+            log!(info: error!("Final statistics for session {session}"));
+        }
+    }
+
+    /// Handles an incoming packet and collects the destination addresses to forward it to
+    ///
+    /// # Errors
+    /// Errors are only returned for fatal conditions; rejected packets are logged and simply yield no destinations.
+    pub fn handle(
+        &mut self,
+        packet: &[u8],
+        source: &SocketAddr,
+        destinations: &mut Vec<SocketAddr>,
+    ) -> Result<(), Error> {
+        // Check for session timeouts
+        self.sessions.expire();
+
+        // Forward packets of existing sessions
+        if self.sessions.is_known(source) {
+            // This is not necessarily fatal, but worth a warning
+            let _ = log!(warn: self.sessions.forward(packet, source, destinations));
+            return Ok(());
+        }
+
+        // Only handshake initiations may start a new session
+        let Some(MessageType::Initiation) = MessageType::of(packet) else {
+            // This is not an error as rogue packets may arrive anytime
+            log!(debug: DropReason::NoSession);
+            return Ok(());
+        };
+        if self.draining {
+            // The relay is shutting down
+            log!(debug: DropReason::Draining);
+            return Ok(());
+        }
+
+        // Only clients permitted by the access control lists may start a new session
+        let client = source.ip();
+        let is_denied = self.config.WGPROXY_DENY.iter().any(|prefix| prefix.contains(&client));
+        let is_allowed = self.config.WGPROXY_ALLOW.is_empty()
+            || self.config.WGPROXY_ALLOW.iter().any(|prefix| prefix.contains(&client));
+        if is_denied || !is_allowed {
+            // This is not an error as rogue packets may arrive anytime
+            log!(debug: DropReason::Denied(client));
+            return Ok(());
+        }
+
+        // Start a new session if it can be admitted and the packet is a valid handshake
+        let Ok(admission) = log!(debug: self.sessions.admit(source)) else {
+            // The session limit has been reached
+            return Ok(());
+        };
+        let Ok(public_key) = log!(debug: self.validator.is_valid_handshake(packet)) else {
+            // This is not an error as rogue packets may arrive anytime
+            return Ok(());
+        };
+
+        let session = Session::new(source, &self.server, &self.config);
+        log!(info: error!("New session {session} matched public key {public_key}"));
+        self.sessions.insert(session, admission);
+
+        // Forward the handshake
+        let _ = log!(warn: self.sessions.forward(packet, source, destinations));
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! A time-windowed replay cache for handshake MACs
+
+use crate::error;
+use crate::log;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+use std::{cmp, mem};
+
+/// An identity hasher for valid aka evenly distributed MAC values
+#[derive(Debug, Clone, Copy, Default)]
+struct MacHasher(u64);
+impl Hasher for MacHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            // Append byte to state
+            self.0 = (self.0 << 8) | (*byte as u64);
+        }
+    }
+
+    fn write_u128(&mut self, value: u128) {
+        // Any 64 bit of a valid MAC are evenly distributed
+        self.0 = value as u64;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+impl BuildHasher for MacHasher {
+    type Hasher = Self;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        *self
+    }
+}
+
+/// A generation of remembered MACs
+#[derive(Debug, Default)]
+struct Generation {
+    /// The remembered MACs
+    macs: HashSet<u128, MacHasher>,
+    /// The time of the most recent insertion
+    newest: Option<Instant>,
+}
+
+/// A replay cache that remembers MACs for a configurable time window
+///
+/// # Retention
+/// The cache consists of two generations: new MACs are inserted into the current generation, which becomes the previous
+/// generation once it is older than the window. The previous generation is dropped on the next rotation, or as soon as
+/// its newest MAC is older than the window. This way, every MAC is remembered for at least the window duration, while
+/// the memory is bounded by the capacity: if the current generation is full, it is rotated early, and the previous
+/// generation is forgotten before its window has passed.
+#[derive(Debug)]
+pub struct ReplayCache {
+    /// The current generation
+    current: Generation,
+    /// The previous generation
+    previous: Generation,
+    /// The time of the last rotation
+    rotated: Instant,
+    /// The minimum retention time
+    window: Duration,
+    /// The maximum amount of MACs per generation
+    generation_capacity: usize,
+}
+impl ReplayCache {
+    /// Creates a new replay cache that remembers up to `capacity` MACs for at least `window`
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            current: Generation::default(),
+            previous: Generation::default(),
+            rotated: Instant::now(),
+            window,
+            generation_capacity: capacity.div_ceil(2),
+        }
+    }
+
+    /// Changes the window and capacity; already remembered MACs are kept
+    pub fn reconfigure(&mut self, window: Duration, capacity: usize) {
+        self.window = window;
+        self.generation_capacity = capacity.div_ceil(2);
+    }
+
+    /// Registers a new MAC, or returns `false` if the MAC is already known
+    pub fn insert(&mut self, mac: u128) -> bool {
+        self.expire();
+        if self.previous.macs.contains(&mac) || self.current.macs.contains(&mac) {
+            // MAC has already been seen before
+            return false;
+        }
+
+        // Rotate early if the current generation is full
+        if self.current.macs.len() >= self.generation_capacity {
+            log!(debug: error!("Replay cache is full, forgetting {} MACs early", self.previous.macs.len()));
+            self.rotate();
+        }
+
+        // Insert the MAC
+        self.current.newest = Some(Instant::now());
+        self.current.macs.insert(mac)
+    }
+
+    /// The remembered MACs with the age of the most recent insertion into their generation
+    ///
+    /// # Note
+    /// The age of a generation's most recent insertion is a lower bound for the age of each of its MACs, so restoring
+    /// this snapshot never forgets a MAC earlier than the original cache would have.
+    pub fn snapshot(&self) -> impl Iterator<Item = (u128, Duration)> {
+        let generations = [&self.current, &self.previous].into_iter();
+        generations.flat_map(|generation| {
+            let age = generation.newest.map(|newest| newest.elapsed()).unwrap_or_default();
+            generation.macs.iter().map(move |mac| (*mac, age))
+        })
+    }
+
+    /// Restores remembered MACs into the previous generation and discards MACs that are older than the window
+    pub fn restore<I>(&mut self, macs: I)
+    where
+        I: IntoIterator<Item = (u128, Duration)>,
+    {
+        for (mac, age) in macs.into_iter().filter(|(_, age)| *age < self.window) {
+            // Stop if the previous generation is full
+            if self.previous.macs.len() >= self.generation_capacity {
+                break;
+            }
+
+            // Restore the MAC and keep track of the most recent insertion
+            let Some(inserted) = Instant::now().checked_sub(age) else {
+                // The MAC is older than the monotonic clock, i.e. from before a reboot
+                continue;
+            };
+            self.previous.newest = cmp::max(self.previous.newest, Some(inserted));
+            self.previous.macs.insert(mac);
+        }
+    }
+
+    /// Rotates or drops the generations if they are older than the window
+    fn expire(&mut self) {
+        if self.rotated.elapsed() >= self.window {
+            // The current generation is old enough to be rotated
+            self.rotate();
+        }
+        if self.previous.newest.is_some_and(|newest| newest.elapsed() >= self.window) {
+            // All MACs of the previous generation are older than the window
+            self.previous = Generation::default();
+        }
+    }
+
+    /// Moves the current generation to the previous generation and drops the old previous generation
+    fn rotate(&mut self) {
+        self.previous = mem::take(&mut self.current);
+        self.rotated = Instant::now();
+    }
+}
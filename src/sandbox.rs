@@ -0,0 +1,409 @@
+//! seccomp and Landlock sandboxing of the relay process
+
+use crate::config::{Config, Sandbox};
+use crate::error;
+use crate::error::Error;
+#[cfg(target_os = "linux")]
+use crate::log;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+/// Restricts the filesystem access and the available syscalls of the current thread and all threads spawned afterwards
+///
+/// # Note
+/// This must be called after the sockets have been bound and the privileges have been dropped, but before any other
+/// thread is spawned, as both seccomp filters and Landlock rulesets are only inherited by new threads.
+#[cfg(target_os = "linux")]
+pub fn enter(configs: &[Config]) -> Result<(), Error> {
+    // The sandbox setting is process-wide, so the first config is authoritative
+    let sandbox = configs.first().map_or(Sandbox::Off, |config| config.WGPROXY_SANDBOX);
+    if sandbox == Sandbox::Off {
+        // Nothing to restrict
+        return Ok(());
+    }
+
+    // Both seccomp and Landlock require `no_new_privs` for unprivileged processes
+    // Safety: `prctl` with `PR_SET_NO_NEW_PRIVS` has no memory safety requirements
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(error!(with: io::Error::last_os_error(), "Failed to set no_new_privs"));
+    }
+
+    // Restrict the filesystem first, as Landlock itself is not on the syscall allowlist
+    landlock::restrict(configs)?;
+    seccomp::restrict(sandbox)?;
+    match sandbox {
+        Sandbox::Errno => log!(info: error!("Entered sandbox; disallowed syscalls fail with EPERM and are logged")),
+        _ => log!(info: error!("Entered sandbox")),
+    };
+    Ok(())
+}
+
+/// Restricts the filesystem access and the available syscalls of the current thread and all threads spawned afterwards
+#[cfg(not(target_os = "linux"))]
+pub fn enter(configs: &[Config]) -> Result<(), Error> {
+    match configs.first().map_or(Sandbox::Off, |config| config.WGPROXY_SANDBOX) {
+        Sandbox::Off => Ok(()),
+        Sandbox::Kill | Sandbox::Errno => Err(error!("Sandboxing is only available on Linux")),
+    }
+}
+
+/// Opens a path for use as Landlock rule anchor, or returns `None` if the path does not exist
+#[cfg(target_os = "linux")]
+fn open_path(path: &Path) -> Result<Option<OwnedFd>, Error> {
+    let path_c = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| error!(with: e, "Invalid sandbox path {}", path.display()))?;
+
+    // Safety: The path is a valid C string for the duration of the call
+    let fd = unsafe { libc::open(path_c.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        let error = io::Error::last_os_error();
+        return match error.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(error!(with: error, "Failed to open sandbox path {}", path.display())),
+        };
+    }
+
+    // Safety: The file descriptor has just been created and is exclusively owned
+    Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// Landlock filesystem restrictions
+#[cfg(target_os = "linux")]
+mod landlock {
+    use crate::config::Config;
+    use crate::error;
+    use crate::error::Error;
+    use crate::log;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::path::Path;
+    use std::{env, fs, io, mem, ptr};
+
+    /// The ruleset attributes as expected by `landlock_create_ruleset`
+    #[repr(C)]
+    struct RulesetAttr {
+        /// The filesystem access rights that are restricted by the ruleset
+        handled_access_fs: u64,
+    }
+    /// A path-beneath rule as expected by `landlock_add_rule`
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        /// The filesystem access rights that are allowed beneath the parent directory
+        allowed_access: u64,
+        /// The parent directory
+        parent_fd: i32,
+    }
+
+    /// Query the supported ABI version instead of creating a ruleset
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    /// The rule type for [`PathBeneathAttr`]
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    /// Write to a file
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    /// Read a file
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    /// List a directory
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// Remove or replace a file
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    /// Create a regular file
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    /// Truncate a file (ABI version 3)
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    /// The directories that are needed to resolve DNS names, with read-only access
+    const READ_ONLY: [&str; 4] = ["/etc", "/lib", "/lib64", "/usr"];
+    /// The resolver configuration files whose target directories need read-only access
+    const RESOLVER_FILES: [&str; 2] = ["/etc/resolv.conf", "/etc/hosts"];
+
+    /// Restricts the filesystem access to the resolver configuration, the config file and the state file directories
+    pub fn restrict(configs: &[Config]) -> Result<(), Error> {
+        // Handle all access rights supported by the running kernel
+        let Some(handled) = handled_access()? else {
+            // Landlock is a best-effort restriction, so we keep the seccomp sandbox
+            log!(warn: error!("Landlock is not supported by the kernel; the filesystem access is not restricted"));
+            return Ok(());
+        };
+        let attr = RulesetAttr { handled_access_fs: handled };
+        // Safety: The attributes are valid for the duration of the call and the size is correct
+        let fd = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr, mem::size_of::<RulesetAttr>(), 0) };
+        if fd < 0 {
+            return Err(error!(with: io::Error::last_os_error(), "Failed to create Landlock ruleset"));
+        }
+        // Safety: The file descriptor has just been created and is exclusively owned
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+
+        // Allow read-only access to the resolver configuration and the NSS modules
+        for path in READ_ONLY {
+            allow(&ruleset, Path::new(path), ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR, handled)?;
+        }
+        for path in RESOLVER_FILES {
+            // The resolver configuration may be a symlink to a file that is replaced at runtime, e.g. by systemd-resolved
+            let Some(parent) = fs::canonicalize(path).ok().and_then(|path| path.parent().map(Path::to_path_buf)) else {
+                continue;
+            };
+            allow(&ruleset, &parent, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR, handled)?;
+        }
+
+        // Allow the config files to be re-read on reload
+        let wg_configs = configs.iter().filter_map(|config| config.WGPROXY_WG_CONFIG.clone());
+        for path in Config::wgproxy_config()?.into_iter().chain(wg_configs) {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            allow(&ruleset, parent, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR, handled)?;
+        }
+
+        // Allow the secret files to be re-read on reload
+        let secrets = env::vars_os().filter(|(name, _)| {
+            let name = name.to_string_lossy();
+            name.starts_with("WGPROXY_") && name.ends_with("_FILE")
+        });
+        for (_, path) in secrets {
+            let parent = match Path::new(&path).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            allow(&ruleset, parent, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR, handled)?;
+        }
+
+        // Allow the state files to be written atomically to their directories
+        for path in configs.iter().filter_map(|config| config.WGPROXY_STATE.as_ref()) {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let access = ACCESS_FS_READ_FILE
+                | ACCESS_FS_WRITE_FILE
+                | ACCESS_FS_READ_DIR
+                | ACCESS_FS_REMOVE_FILE
+                | ACCESS_FS_MAKE_REG
+                | ACCESS_FS_TRUNCATE;
+            allow(&ruleset, parent, access, handled)?;
+        }
+
+        // Enforce the ruleset
+        // Safety: `landlock_restrict_self` has no memory safety requirements
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } != 0 {
+            return Err(error!(with: io::Error::last_os_error(), "Failed to enforce Landlock ruleset"));
+        }
+        Ok(())
+    }
+
+    /// Gets the access rights supported by the running kernel, or `None` if Landlock is not supported
+    fn handled_access() -> Result<Option<u64>, Error> {
+        // Safety: Querying the ABI version has no memory safety requirements
+        let version = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset, ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION)
+        };
+        match version {
+            ..0 => match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENOSYS | libc::EOPNOTSUPP) => Ok(None),
+                _ => Err(error!(with: io::Error::last_os_error(), "Failed to query Landlock ABI version")),
+            },
+            // ABI 1 supports `EXECUTE` to `MAKE_SYM`, ABI 2 adds `REFER`, ABI 3 adds `TRUNCATE`, ABI 5 adds `IOCTL_DEV`
+            0..=1 => Ok(Some((1 << 13) - 1)),
+            2 => Ok(Some((1 << 14) - 1)),
+            3..=4 => Ok(Some((1 << 15) - 1)),
+            _ => Ok(Some((1 << 16) - 1)),
+        }
+    }
+
+    /// Allows the given access rights beneath the given directory if it exists
+    fn allow(ruleset: &OwnedFd, path: &Path, access: u64, handled: u64) -> Result<(), Error> {
+        let Some(parent) = super::open_path(path)? else {
+            // Nothing to allow
+            return Ok(());
+        };
+
+        // Only allow the access rights that are also handled, as the rule is rejected otherwise
+        let attr = PathBeneathAttr { allowed_access: access & handled, parent_fd: parent.as_raw_fd() };
+        // Safety: The attributes are valid for the duration of the call
+        let result =
+            unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset.as_raw_fd(), RULE_PATH_BENEATH, &attr, 0) };
+        if result != 0 {
+            return Err(error!(with: io::Error::last_os_error(), "Failed to add Landlock rule for {}", path.display()));
+        }
+        Ok(())
+    }
+}
+
+/// seccomp syscall filtering
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use crate::config::Sandbox;
+    use crate::error;
+    use crate::error::Error;
+    use std::io;
+
+    /// The audit architecture of the current target
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    /// The audit architecture of the current target
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// The offset of `seccomp_data::nr`
+    const OFFSET_NR: u32 = 0;
+    /// The offset of `seccomp_data::arch`
+    const OFFSET_ARCH: u32 = 4;
+    /// The offset of the lower half of `seccomp_data::args[1]`
+    const OFFSET_ARG1: u32 = 24;
+
+    /// The syscalls the relay may issue after startup
+    const ALLOWED: &[libc::c_long] = &[
+        // Memory management and threading
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_futex,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_tgkill,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        // Signals
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_restart_syscall,
+        // Clock
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        libc::SYS_gettimeofday,
+        libc::SYS_getrandom,
+        // Generic I/O, e.g. for logging and the state file
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_writev,
+        libc::SYS_close,
+        libc::SYS_fcntl,
+        libc::SYS_lseek,
+        libc::SYS_openat,
+        libc::SYS_newfstatat,
+        libc::SYS_fstat,
+        libc::SYS_statx,
+        libc::SYS_faccessat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_ppoll,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_stat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_access,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rename,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_poll,
+        // Sockets, e.g. for packet forwarding, DNS resolution, handover and systemd notifications
+        libc::SYS_socket,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_accept,
+        libc::SYS_accept4,
+        libc::SYS_shutdown,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_getsockopt,
+        libc::SYS_setsockopt,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_recvmmsg,
+        // The io-uring backend
+        #[cfg(feature = "io-uring")]
+        libc::SYS_io_uring_setup,
+        #[cfg(feature = "io-uring")]
+        libc::SYS_io_uring_enter,
+        #[cfg(feature = "io-uring")]
+        libc::SYS_io_uring_register,
+    ];
+
+    /// Creates a BPF statement
+    const fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+    }
+
+    /// Creates a BPF conditional jump
+    const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code: code as u16, jt, jf, k }
+    }
+
+    /// Installs a seccomp filter that kills the process on any syscall that is not on the allowlist, or lets the syscall
+    /// fail with `EPERM` in [`Sandbox::Errno`] mode
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn restrict(sandbox: Sandbox) -> Result<(), Error> {
+        // In errno mode, the kernel logs the denied syscalls so that a missing allowlist entry can be spotted
+        let (deny, flags) = match sandbox {
+            Sandbox::Errno => (libc::SECCOMP_RET_ERRNO | libc::EPERM as u32, libc::SECCOMP_FILTER_FLAG_LOG),
+            Sandbox::Off | Sandbox::Kill => (libc::SECCOMP_RET_KILL_PROCESS, 0),
+        };
+
+        // Kill the process if the syscall is issued for a foreign architecture
+        let mut filter = vec![
+            statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, OFFSET_ARCH),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, OFFSET_NR),
+        ];
+
+        // Allow `ioctl` only to toggle the non-blocking mode for the handover and to query the pending datagram size for
+        //  the DNS resolver
+        filter.extend([
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, libc::SYS_ioctl as u32, 0, 5),
+            statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, OFFSET_ARG1),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, libc::FIONBIO as u32, 1, 0),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, libc::FIONREAD as u32, 0, 1),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+            statement(libc::BPF_RET | libc::BPF_K, deny),
+        ]);
+
+        // Allow the syscalls on the allowlist and deny them otherwise
+        for syscall in ALLOWED {
+            filter.extend([
+                jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, *syscall as u32, 0, 1),
+                statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+            ]);
+        }
+        filter.push(statement(libc::BPF_RET | libc::BPF_K, deny));
+
+        // Install the filter
+        let len = u16::try_from(filter.len()).map_err(|e| error!(with: e, "seccomp filter is too large"))?;
+        let program = libc::sock_fprog { len, filter: filter.as_mut_ptr() };
+        // Safety: The program is valid for the duration of the call; the kernel copies it
+        if unsafe { libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, flags, &program) } != 0 {
+            return Err(error!(with: io::Error::last_os_error(), "Failed to install seccomp filter"));
+        }
+        Ok(())
+    }
+
+    /// Installs a seccomp filter that kills the process on any syscall that is not on the allowlist, or lets the syscall
+    /// fail with `EPERM` in [`Sandbox::Errno`] mode
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn restrict(_sandbox: Sandbox) -> Result<(), Error> {
+        Err(error!("Sandboxing is not available on this architecture"))
+    }
+}
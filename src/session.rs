@@ -1,11 +1,14 @@
 //! The relay session
 
-use crate::config::Config;
-use crate::error;
-use crate::error::Error;
+use crate::config::{Config, IdlePolicy, TakeoverPolicy};
+use crate::error::DropReason;
+use crate::log;
+use crate::packet::{self, MessageType};
+use crate::state::SessionState;
+use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
-use std::time::Instant;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 use std::{cmp, fmt};
 
 /// Extends [`SocketAddr`]
@@ -38,11 +41,18 @@ impl SocketAddrExt for SocketAddr {
     }
 }
 
+/// A forwarding direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// From the client to the server
+    Uplink,
+    /// From the server to the client
+    Downlink,
+}
+
 /// A relay session
 #[derive(Debug)]
-pub struct Session<'a> {
-    /// The forwarding socket
-    socket: &'a UdpSocket,
+pub struct Session {
     /// The client address for this session
     client_address: SocketAddr,
     /// The server address for this session
@@ -51,15 +61,49 @@ pub struct Session<'a> {
     last_uplink: Instant,
     /// The last downlink atime
     last_downlink: Instant,
+    /// The time of the last completed handshake
+    last_handshake: Instant,
+    /// Whether the peers have completed at least one handshake
+    established: bool,
+    /// The most recent sender indices announced by the client
+    client_indices: VecDeque<u32>,
+    /// The direction of the last initiation that has not been answered yet
+    pending_initiation: Option<Direction>,
+    /// The grace period after `REJECT_AFTER_TIME` if re-key tracking is enabled
+    rekey_grace: Option<Duration>,
+    /// The uplink timeout
+    timeout_uplink: Duration,
+    /// The downlink timeout
+    timeout_downlink: Duration,
+    /// The idle policy
+    idle_policy: IdlePolicy,
+    /// The amount of bytes received from the client
+    uplink_bytes: u64,
+    /// The amount of packets received from the client
+    uplink_packets: u64,
+    /// The amount of bytes forwarded to the client
+    downlink_bytes: u64,
+    /// The amount of packets forwarded to the client
+    downlink_packets: u64,
+    /// The sender index of the last handshake response forwarded to the client
+    response_index: Option<u32>,
+    /// Whether the client has demonstrated reachability
+    reachable: bool,
+    /// The amplification packet limit for unreachable clients, or `0` if the guard is disabled
+    amplification_limit: u64,
+    /// The amplification byte ratio for unreachable clients
+    amplification_ratio: u64,
+    /// The amount of packets dropped by the amplification guard
+    dropped: u64,
 }
-impl<'a> Session<'a> {
-    /// Creates a new relay session with the given incoming handshake packet
-    pub fn new(client_address: &SocketAddr, config: &Config, socket: &'a UdpSocket) -> Result<Self, Error> {
-        // Resolve server address
-        let mut server_addresses = (config.WGPROXY_SERVER.to_socket_addrs())
-            .map_err(|e| error!(with: e, "Failed to resolve server address"))?;
-        let server_address = server_addresses.next().ok_or(error!("Failed to resolve server address"))?;
+impl Session {
+    /// WireGuard's `REJECT_AFTER_TIME` after which a key is not accepted anymore
+    const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+    /// The maximum amount of client sender indices to retain (WireGuard keeps a previous, current and next keypair)
+    const CLIENT_INDICES_MAX: usize = 3;
 
+    /// Creates a new relay session between the given client and the resolved server address
+    pub fn new(client_address: &SocketAddr, server_address: &SocketAddr, config: &Config) -> Self {
         // Canonicalize socket addresses so we always have the same family as our listening socket
         let server_address = server_address.canonical(&config.WGPROXY_LISTEN);
         let client_address = client_address.canonical(&config.WGPROXY_LISTEN);
@@ -67,49 +111,263 @@ impl<'a> Session<'a> {
         // Init self
         let last_uplink = Instant::now();
         let last_downlink = Instant::now();
-        Ok(Self { socket, client_address, server_address, last_uplink, last_downlink })
+        Self {
+            client_address,
+            server_address,
+            last_uplink,
+            last_downlink,
+            last_handshake: Instant::now(),
+            established: false,
+            client_indices: VecDeque::with_capacity(Self::CLIENT_INDICES_MAX),
+            pending_initiation: None,
+            rekey_grace: config.WGPROXY_REKEY_GRACE,
+            timeout_uplink: config.WGPROXY_TIMEOUT_UPLINK,
+            timeout_downlink: config.WGPROXY_TIMEOUT_DOWNLINK,
+            idle_policy: config.WGPROXY_IDLE_POLICY,
+            uplink_bytes: 0,
+            uplink_packets: 0,
+            downlink_bytes: 0,
+            downlink_packets: 0,
+            response_index: None,
+            reachable: false,
+            amplification_limit: config.WGPROXY_AMPLIFICATION_LIMIT,
+            amplification_ratio: config.WGPROXY_AMPLIFICATION_RATIO,
+            dropped: 0,
+        }
+    }
+
+    /// Restores a session from a snapshot, or returns `None` if the snapshot is stale
+    pub fn restore(state: &SessionState, server_address: &SocketAddr, config: &Config) -> Option<Self> {
+        // Discard the session if the server address has changed
+        let mut session = Self::new(&state.client_address, server_address, config);
+        if session.server_address != state.server_address {
+            return None;
+        }
+
+        // Restore the timestamps
+        let now = Instant::now();
+        let (Some(last_uplink), Some(last_downlink), Some(last_handshake)) = (
+            now.checked_sub(state.last_uplink),
+            now.checked_sub(state.last_downlink),
+            now.checked_sub(state.last_handshake),
+        ) else {
+            // The timestamps are older than the monotonic clock, i.e. from before a reboot
+            return None;
+        };
+        session.last_uplink = last_uplink;
+        session.last_downlink = last_downlink;
+        session.last_handshake = last_handshake;
+
+        // Restore the handshake state
+        session.established = state.established;
+        session.response_index = state.response_index;
+        session.reachable = state.reachable;
+        for index in &state.client_indices {
+            session.register_index(*index);
+        }
+
+        // Discard the session if it has expired in the meantime
+        match session.is_expired() {
+            true => None,
+            false => Some(session),
+        }
+    }
+
+    /// Applies the timeouts and limits of a reloaded config; the server address is kept to not break the session
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.rekey_grace = config.WGPROXY_REKEY_GRACE;
+        self.timeout_uplink = config.WGPROXY_TIMEOUT_UPLINK;
+        self.timeout_downlink = config.WGPROXY_TIMEOUT_DOWNLINK;
+        self.idle_policy = config.WGPROXY_IDLE_POLICY;
+        self.amplification_limit = config.WGPROXY_AMPLIFICATION_LIMIT;
+        self.amplification_ratio = config.WGPROXY_AMPLIFICATION_RATIO;
+    }
+
+    /// Captures the persistent state of this session
+    pub fn snapshot(&self) -> SessionState {
+        SessionState {
+            client_address: self.client_address,
+            server_address: self.server_address,
+            client_indices: self.client_indices.iter().copied().collect(),
+            last_uplink: self.last_uplink.elapsed(),
+            last_downlink: self.last_downlink.elapsed(),
+            last_handshake: self.last_handshake.elapsed(),
+            established: self.established,
+            response_index: self.response_index,
+            reachable: self.reachable,
+        }
     }
 
-    /// Forward an incoming packet if appropriate
-    pub fn forward(&mut self, packet: &[u8], source: &SocketAddr) -> Result<(), Error> {
+    /// Accounts an incoming packet and returns the address to forward it to, or `None` if the packet is dropped
+    pub fn forward(&mut self, packet: &[u8], source: &SocketAddr) -> Result<Option<SocketAddr>, DropReason> {
         // Route packet accordingly
         if self.client_address.eq(source) {
+            // A transport packet for the forwarded handshake response proves that the client can receive our packets, as
+            //  only the recipient of the response knows its sender index
+            let is_transport = MessageType::of(packet) == Some(MessageType::Transport);
+            if is_transport && self.response_index.is_some() && packet::receiver_index(packet) == self.response_index {
+                self.reachable = true;
+            }
+
             // Forward client packet to server
-            self.socket.send_to(packet, &self.server_address)?;
+            self.track_handshake(packet, Direction::Uplink);
+            self.uplink_bytes = self.uplink_bytes.saturating_add(packet.len() as u64);
+            self.uplink_packets = self.uplink_packets.saturating_add(1);
             self.last_uplink = Instant::now();
-            Ok(())
+            Ok(Some(self.server_address))
         } else if self.server_address.eq(source) {
+            // Enforce the amplification guard
+            if self.is_amplifying(packet) {
+                // Drop the packet; this is not an error as it is expected behaviour under a spoofing attack
+                self.dropped = self.dropped.saturating_add(1);
+                log!(debug: DropReason::Amplification(self.client_address));
+                return Ok(None);
+            }
+
             // Forward server packet to client
-            self.socket.send_to(packet, &self.client_address)?;
+            self.track_handshake(packet, Direction::Downlink);
+            self.downlink_bytes = self.downlink_bytes.saturating_add(packet.len() as u64);
+            self.downlink_packets = self.downlink_packets.saturating_add(1);
+            if MessageType::of(packet) == Some(MessageType::Response) {
+                self.response_index = packet::sender_index(packet);
+            }
             self.last_downlink = Instant::now();
-            Ok(())
+            Ok(Some(self.client_address))
         } else {
             // Cannot associate packet source
-            Err(error!("Unknown packet from {source}"))
+            Err(DropReason::UnknownSource(*source))
+        }
+    }
+
+    /// Tracks handshake initiations and responses to detect completed re-keys
+    fn track_handshake(&mut self, packet: &[u8], direction: Direction) {
+        match (MessageType::of(packet), self.pending_initiation) {
+            (Some(MessageType::Initiation), _) => self.pending_initiation = Some(direction),
+            (Some(MessageType::Response), Some(initiation)) if initiation != direction => {
+                // The response answers the pending initiation, so the peers have completed a handshake
+                self.last_handshake = Instant::now();
+                self.established = true;
+                self.pending_initiation = None;
+                // This is a hot path, so the message is only formatted if it is logged
+                log!(debug: format_args!("Session {self} has completed a handshake"));
+            }
+            _ => (),
+        }
+    }
+
+    /// Registers a new client sender index and returns the evicted oldest index, if any
+    pub fn register_index(&mut self, index: u32) -> Option<u32> {
+        // Ignore already known indices
+        if self.client_indices.contains(&index) {
+            return None;
+        }
+
+        // Evict the oldest index if necessary
+        let evicted = match self.client_indices.len() {
+            Self::CLIENT_INDICES_MAX => self.client_indices.pop_front(),
+            _ => None,
+        };
+        self.client_indices.push_back(index);
+        evicted
+    }
+
+    /// The client sender indices of this session
+    pub fn client_indices(&self) -> impl Iterator<Item = u32> {
+        self.client_indices.iter().copied()
+    }
+
+    /// The client address of this session
+    pub fn client_address(&self) -> &SocketAddr {
+        &self.client_address
+    }
+
+    /// The server address of this session
+    pub fn server_address(&self) -> &SocketAddr {
+        &self.server_address
+    }
+
+    /// The most recent activity in either direction
+    pub fn last_activity(&self) -> Instant {
+        cmp::max(self.last_uplink, self.last_downlink)
+    }
+
+    /// Whether the peers have not completed a handshake yet
+    pub fn is_pending(&self) -> bool {
+        !self.established
+    }
+
+    /// Whether a handshake from a new client may take over this session according to the given policy
+    pub fn may_takeover(&self, policy: TakeoverPolicy) -> bool {
+        match policy {
+            TakeoverPolicy::Never => false,
+            TakeoverPolicy::Idle(idle) => self.last_uplink.elapsed() > idle,
+            TakeoverPolicy::Always => true,
+        }
+    }
+
+    /// Whether forwarding the given packet to the client would exceed the amplification budget
+    fn is_amplifying(&self, packet: &[u8]) -> bool {
+        // The guard is only active until the client has demonstrated reachability
+        if self.amplification_limit == 0 || self.reachable {
+            return false;
+        }
+
+        // Check both the packet and the byte budget
+        let byte_budget = self.uplink_bytes.saturating_mul(self.amplification_ratio);
+        let downlink_bytes = self.downlink_bytes.saturating_add(packet.len() as u64);
+        self.downlink_packets >= self.amplification_limit || downlink_bytes > byte_budget
+    }
+
+    /// Whether the session has expired according to its idle policy
+    pub fn is_expired(&self) -> bool {
+        // Sessions without a timely re-key cannot carry valid traffic anymore
+        if self.is_rekey_overdue() {
+            return true;
+        }
+
+        // Apply the idle policy
+        let uplink_expired = self.last_uplink.elapsed() > self.timeout_uplink;
+        let downlink_expired = self.last_downlink.elapsed() > self.timeout_downlink;
+        match self.idle_policy {
+            // Keep-alives should be symmetrical, so by default we expire the session if either direction times out – if
+            //  one atime drifts beyond the timeout threshold, something is probably wrong, even if the other atime is
+            //  updated.
+            IdlePolicy::Min => uplink_expired || downlink_expired,
+            IdlePolicy::Max => uplink_expired && downlink_expired,
+            IdlePolicy::Uplink => uplink_expired,
+            IdlePolicy::Downlink => downlink_expired,
         }
     }
 
-    /// The latest atime of this session
-    pub fn atime(&self) -> Instant {
-        // Keep-alives should be symmetrical, so we use the **older** atime as reference – if one atime drifts beyond
-        //  the timeout threshold, something is probably wrong, even if the other atime is updated.
-        cmp::min(self.last_uplink, self.last_downlink)
+    /// Whether the session has not completed a re-key within `REJECT_AFTER_TIME` plus the grace period
+    fn is_rekey_overdue(&self) -> bool {
+        let Some(grace) = self.rekey_grace else {
+            // Re-key tracking is disabled
+            return false;
+        };
+        self.last_handshake.elapsed() > Self::REJECT_AFTER_TIME.saturating_add(grace)
     }
 }
-impl Display for Session<'_> {
+impl Display for Session {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         // Encode some fields for better readability
-        let socket = self.socket.local_addr().ok();
         let last_uplink = self.last_uplink.elapsed();
         let last_downlink = self.last_downlink.elapsed();
+        let last_handshake = self.last_handshake.elapsed();
 
         // Format struct
         f.debug_struct("Session")
-            .field("socket", &socket)
             .field("client_address", &self.client_address)
             .field("server_address", &self.server_address)
             .field("last_uplink", &last_uplink)
             .field("last_downlink", &last_downlink)
+            .field("last_handshake", &last_handshake)
+            .field("reachable", &self.reachable)
+            .field("uplink_packets", &self.uplink_packets)
+            .field("uplink_bytes", &self.uplink_bytes)
+            .field("downlink_packets", &self.downlink_packets)
+            .field("downlink_bytes", &self.downlink_bytes)
+            .field("dropped", &self.dropped)
             .finish()
     }
 }
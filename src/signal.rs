@@ -0,0 +1,66 @@
+//! Termination and reload signal handling
+
+use crate::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether a termination signal has been received
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+/// Whether a reload signal has been received and not yet handled
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Installs the `SIGTERM` and `SIGINT` handlers, which request a graceful shutdown of the [`crate::eventloop`], and the
+/// `SIGHUP` handler, which requests a config reload
+#[cfg(target_os = "linux")]
+pub fn install() -> Result<(), Error> {
+    use std::{io, mem, ptr};
+
+    /// The signal handler; this only performs an atomic store and is therefore async-signal-safe
+    extern "C" fn handle(signal: libc::c_int) {
+        match signal {
+            libc::SIGHUP => RELOAD.store(true, Ordering::Relaxed),
+            _ => TERMINATED.store(true, Ordering::Relaxed),
+        }
+    }
+
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // Safety: `sigaction` is a plain C struct where all-zero is a valid bit pattern
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+
+        // Safety: The action is fully initialized and the handler is async-signal-safe
+        if unsafe { libc::sigaction(signal, &action, ptr::null_mut()) } != 0 {
+            return Err(crate::error!(with: io::Error::last_os_error(), "Failed to install signal handler"));
+        }
+    }
+    Ok(())
+}
+
+/// Installs the `SIGTERM` and `SIGINT` handlers, which request a graceful shutdown of the [`crate::eventloop`], and the
+/// `SIGHUP` handler, which requests a config reload
+#[cfg(not(target_os = "linux"))]
+pub fn install() -> Result<(), Error> {
+    // Signal handling is not available, so the process is simply terminated by the default handlers
+    crate::log!(info: crate::error!("Graceful shutdown and config reload are only available on Linux"));
+    Ok(())
+}
+
+/// Requests a graceful shutdown of the [`crate::eventloop`]
+pub fn terminate() {
+    TERMINATED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a graceful shutdown has been requested
+pub fn is_terminated() -> bool {
+    TERMINATED.load(Ordering::Relaxed)
+}
+
+/// Requests a config reload of the [`crate::eventloop`]
+pub fn reload() {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
+/// Whether a config reload has been requested; this resets the request
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::Relaxed)
+}
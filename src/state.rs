@@ -0,0 +1,243 @@
+//! The persistent relay state
+
+use crate::backend::WAKEUP_INTERVAL;
+use crate::error;
+use crate::error::Error;
+use crate::log;
+use crate::relay::Relay;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, thread};
+
+/// The persistent state of a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionState {
+    /// The client address
+    pub client_address: SocketAddr,
+    /// The server address
+    pub server_address: SocketAddr,
+    /// The most recent sender indices announced by the client
+    pub client_indices: Vec<u32>,
+    /// The time since the last uplink packet
+    pub last_uplink: Duration,
+    /// The time since the last downlink packet
+    pub last_downlink: Duration,
+    /// The time since the last completed handshake
+    pub last_handshake: Duration,
+    /// Whether the peers have completed at least one handshake
+    pub established: bool,
+    /// The sender index of the last handshake response forwarded to the client
+    pub response_index: Option<u32>,
+    /// Whether the client has demonstrated reachability
+    pub reachable: bool,
+}
+
+/// A snapshot of the relay state
+///
+/// # Format
+/// The state is stored as a line-based text file. All ages are in milliseconds relative to the snapshot time:
+/// ```text
+/// wgproxy-state 2
+/// time <unix time in milliseconds>
+/// mac <MAC1 as 32 hex digits> <age>
+/// session <client> <server> <uplink age> <downlink age> <handshake age> <established> <response index as hex value or "-"> <reachable> <client indices as comma-separated hex values or "-">
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    /// The remembered handshake MAC1s and their ages
+    pub macs: Vec<(u128, Duration)>,
+    /// The active sessions
+    pub sessions: Vec<SessionState>,
+}
+impl State {
+    /// The format header
+    const HEADER: &str = "wgproxy-state 2";
+
+    /// Loads the state from the given path, or returns `None` if the file does not exist
+    ///
+    /// # Staleness
+    /// All ages are advanced by the time that has passed since the snapshot was written.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(error!(with: e, "Failed to read state file {}", path.display())),
+        };
+        let state = Self::parse(&text).map_err(|e| error!("Invalid state file {}: {e}", path.display()))?;
+        Ok(Some(state))
+    }
+
+    /// Atomically writes the state to the given path
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        // Write to a temporary file first, so that a crash never leaves a truncated state file behind
+        let mut tmp_path = OsString::from(path);
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, self.to_string())
+            .map_err(|e| error!(with: e, "Failed to write state file {}", PathBuf::from(&tmp_path).display()))?;
+        fs::rename(&tmp_path, path).map_err(|e| error!(with: e, "Failed to write state file {}", path.display()))
+    }
+
+    /// Parses the state and advances all ages by the time since the snapshot
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index.saturating_add(1), line));
+        let Some((_, Self::HEADER)) = lines.next() else {
+            // The file has an unknown format or version
+            return Err(error!("Unsupported state file format"));
+        };
+
+        // Parse the entries
+        let mut state = Self::default();
+        let mut elapsed = Duration::ZERO;
+        for (number, line) in lines {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                None => continue,
+                Some("time") => {
+                    let time = Duration::from_millis(Self::field(&mut fields, number)?);
+                    elapsed = Self::unix_time().saturating_sub(time);
+                }
+                Some("mac") => {
+                    let mac: String = Self::field(&mut fields, number)?;
+                    let mac = u128::from_str_radix(&mac, 16)
+                        .map_err(|e| error!(with: e, "Invalid MAC in state file line {number}"))?;
+                    let age = Duration::from_millis(Self::field(&mut fields, number)?);
+                    state.macs.push((mac, age));
+                }
+                Some("session") => {
+                    let session = Self::parse_session(&mut fields, number)?;
+                    state.sessions.push(session);
+                }
+                Some(kind) => return Err(error!(r#"Unknown entry "{kind}" in state file line {number}"#)),
+            }
+        }
+
+        // Advance all ages by the time since the snapshot
+        for (_, age) in state.macs.iter_mut() {
+            *age = age.saturating_add(elapsed);
+        }
+        for session in state.sessions.iter_mut() {
+            session.last_uplink = session.last_uplink.saturating_add(elapsed);
+            session.last_downlink = session.last_downlink.saturating_add(elapsed);
+            session.last_handshake = session.last_handshake.saturating_add(elapsed);
+        }
+        Ok(state)
+    }
+
+    /// Parses the fields of a session entry
+    fn parse_session(fields: &mut SplitWhitespace, number: usize) -> Result<SessionState, Error> {
+        let client_address = Self::field(fields, number)?;
+        let server_address = Self::field(fields, number)?;
+        let last_uplink = Duration::from_millis(Self::field(fields, number)?);
+        let last_downlink = Duration::from_millis(Self::field(fields, number)?);
+        let last_handshake = Duration::from_millis(Self::field(fields, number)?);
+        let established = Self::field(fields, number)?;
+        let response_index: String = Self::field(fields, number)?;
+        let response_index = match response_index.as_str() {
+            "-" => None,
+            response_index => Some(
+                u32::from_str_radix(response_index, 16)
+                    .map_err(|e| error!(with: e, "Invalid response index in state file line {number}"))?,
+            ),
+        };
+        let reachable = Self::field(fields, number)?;
+
+        // Parse the client indices
+        let client_indices: String = Self::field(fields, number)?;
+        let client_indices = match client_indices.as_str() {
+            "-" => Vec::new(),
+            client_indices => (client_indices.split(','))
+                .map(|index| u32::from_str_radix(index, 16))
+                .collect::<Result<_, _>>()
+                .map_err(|e| error!(with: e, "Invalid client index in state file line {number}"))?,
+        };
+
+        Ok(SessionState {
+            client_address,
+            server_address,
+            client_indices,
+            last_uplink,
+            last_downlink,
+            last_handshake,
+            established,
+            response_index,
+            reachable,
+        })
+    }
+
+    /// Parses the next field of an entry
+    fn field<T>(fields: &mut SplitWhitespace, number: usize) -> Result<T, Error>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + 'static,
+    {
+        let field = fields.next().ok_or_else(|| error!("Missing field in state file line {number}"))?;
+        field.parse::<T>().map_err(|e| error!(with: e, r#"Invalid field "{field}" in state file line {number}"#))
+    }
+
+    /// The current unix time
+    fn unix_time() -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", Self::HEADER)?;
+        writeln!(f, "time {}", Self::unix_time().as_millis())?;
+        for (mac, age) in &self.macs {
+            writeln!(f, "mac {mac:032x} {}", age.as_millis())?;
+        }
+        for session in &self.sessions {
+            // Encode the client indices
+            let client_indices: Vec<_> = session.client_indices.iter().map(|index| format!("{index:08x}")).collect();
+            let client_indices = match client_indices.is_empty() {
+                true => "-".to_string(),
+                false => client_indices.join(","),
+            };
+
+            // Encode the response index
+            let response_index = match session.response_index {
+                Some(index) => format!("{index:08x}"),
+                None => "-".to_string(),
+            };
+
+            // Write the entry
+            writeln!(
+                f,
+                "session {} {} {} {} {} {} {response_index} {} {client_indices}",
+                session.client_address,
+                session.server_address,
+                session.last_uplink.as_millis(),
+                session.last_downlink.as_millis(),
+                session.last_handshake.as_millis(),
+                session.established,
+                session.reachable,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Periodically snapshots the relay state to the given path until `stop` is set
+pub fn snapshot_loop(path: &Path, interval: Duration, relay: &Mutex<Relay>, stop: &AtomicBool) -> Result<(), Error> {
+    let mut last_snapshot = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        // Sleep in short steps to notice a stop request quickly
+        thread::sleep(WAKEUP_INTERVAL);
+        if last_snapshot.elapsed() < interval {
+            continue;
+        }
+
+        // This is not necessarily fatal, but worth a warning
+        let state = Relay::lock(relay)?.snapshot();
+        let _ = log!(warn: state.save(path));
+        last_snapshot = Instant::now();
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! systemd socket activation and service notifications
+//!
+//! See <https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html> and
+//! <https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html> for more information.
+
+#[cfg(target_os = "linux")]
+use crate::error;
+use crate::error::Error;
+#[cfg(target_os = "linux")]
+use crate::log;
+use std::net::UdpSocket;
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{self, UnixDatagram};
+use std::time::{Duration, Instant};
+#[cfg(target_os = "linux")]
+use std::{env, io, mem, process, ptr};
+
+/// The first file descriptor passed by systemd
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the sockets passed by systemd socket activation, or returns `None` if the process has not been socket-activated
+#[cfg(target_os = "linux")]
+pub fn listen_fds() -> Result<Option<Vec<UdpSocket>>, Error> {
+    let (pid, fds) = (env::var_os("LISTEN_PID"), env::var_os("LISTEN_FDS"));
+
+    // Do not pass the variables on to child processes, e.g. a handover successor, like `sd_listen_fds(1)`
+    // Safety: This runs during startup before any worker thread is spawned, and nothing else accesses these variables
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        // The process has not been socket-activated
+        return Ok(None);
+    };
+    let pid = pid.to_string_lossy();
+    let fds = fds.to_string_lossy();
+    let true = pid.parse::<u32>().ok() == Some(process::id()) else {
+        // The sockets have been passed to another process, e.g. our parent
+        return Ok(None);
+    };
+    let count = fds.parse::<RawFd>().map_err(|e| error!(with: e, r#"Invalid socket count "{fds}""#))?;
+
+    // Take ownership of the passed sockets
+    let mut sockets = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count) {
+        // Only datagram sockets can be used
+        let mut kind: libc::c_int = 0;
+        let mut kind_len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // Safety: The pointers are valid for the duration of the call
+        let result = unsafe {
+            libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, ptr::from_mut(&mut kind).cast(), &mut kind_len)
+        };
+        if result != 0 {
+            return Err(error!(with: io::Error::last_os_error(), "Passed file descriptor {fd} is not a socket"));
+        }
+        if kind != libc::SOCK_DGRAM {
+            return Err(error!("Passed file descriptor {fd} is not a UDP socket"));
+        }
+
+        // Do not leak the socket to child processes
+        // Safety: `fcntl` with `F_SETFD` has no memory safety requirements
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        // Safety: systemd has passed the file descriptor to us exclusively
+        sockets.push(unsafe { UdpSocket::from_raw_fd(fd) });
+    }
+    Ok(Some(sockets))
+}
+
+/// Takes the sockets passed by systemd socket activation, or returns `None` if the process has not been socket-activated
+#[cfg(not(target_os = "linux"))]
+pub fn listen_fds() -> Result<Option<Vec<UdpSocket>>, Error> {
+    Ok(None)
+}
+
+/// A service manager notifier
+#[derive(Debug)]
+pub struct Notifier {
+    /// The notification socket and address, if running under systemd
+    #[cfg(target_os = "linux")]
+    socket: Option<(UnixDatagram, net::SocketAddr)>,
+    /// The watchdog interval, if the watchdog is enabled
+    watchdog: Option<Duration>,
+    /// The time of the last watchdog ping
+    last_ping: Instant,
+    /// The last reported amount of sessions
+    last_sessions: Option<usize>,
+    /// Whether the watchdog pings are withheld as a worker has stopped responding
+    stalled: bool,
+}
+impl Notifier {
+    /// Creates a new notifier from the `NOTIFY_SOCKET`, `WATCHDOG_USEC` and `WATCHDOG_PID` environment variables
+    #[cfg(target_os = "linux")]
+    pub fn from_env() -> Result<Self, Error> {
+        // Connect to the notification socket; names starting with `@` are in the abstract namespace
+        let socket = match env::var_os("NOTIFY_SOCKET") {
+            None => None,
+            Some(path) => {
+                let address = match path.as_encoded_bytes().strip_prefix(b"@") {
+                    Some(name) => net::SocketAddr::from_abstract_name(name),
+                    None => net::SocketAddr::from_pathname(&path),
+                };
+                let address = address.map_err(|e| error!(with: e, "Invalid notification socket {path:?}"))?;
+                Some((UnixDatagram::unbound()?, address))
+            }
+        };
+
+        // Get the watchdog interval if it is meant for us
+        let watchdog_pid = env::var("WATCHDOG_PID").ok().map(|pid| pid.parse::<u32>().ok());
+        let watchdog = match (env::var("WATCHDOG_USEC"), watchdog_pid) {
+            (Ok(usec), None) => Some(usec),
+            (Ok(usec), Some(pid)) if pid == Some(process::id()) => Some(usec),
+            _ => None,
+        };
+        let watchdog =
+            watchdog.map(|usec| usec.parse::<u64>().map_err(|e| error!(with: e, "Invalid watchdog interval")));
+        let watchdog = watchdog.transpose()?.map(Duration::from_micros);
+        Ok(Self { socket, watchdog, last_ping: Instant::now(), last_sessions: None, stalled: false })
+    }
+
+    /// Creates a new notifier from the `NOTIFY_SOCKET`, `WATCHDOG_USEC` and `WATCHDOG_PID` environment variables
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(Self { watchdog: None, last_ping: Instant::now(), last_sessions: None, stalled: false })
+    }
+
+    /// Sends a notification to the service manager, if any
+    #[cfg(target_os = "linux")]
+    pub fn notify(&self, state: &str) {
+        if let Some((socket, address)) = &self.socket {
+            // This is not necessarily fatal, but worth a warning
+            let result = socket.send_to_addr(state.as_bytes(), address);
+            let _ = log!(warn: result.map_err(|e| error!(with: e, "Failed to notify service manager")));
+        }
+    }
+
+    /// Sends a notification to the service manager, if any
+    #[cfg(not(target_os = "linux"))]
+    pub fn notify(&self, _state: &str) {
+        // There is no service manager to notify
+    }
+
+    /// Pings the watchdog if necessary and reports the amount of sessions if it has changed
+    ///
+    /// # Watchdog
+    /// The watchdog is only pinged if the least recent worker heartbeat is younger than the watchdog interval, so that
+    /// the service manager restarts the relay if a worker is wedged.
+    pub fn tick(&mut self, sessions: usize, heartbeat_age: Duration) {
+        // Ping the watchdog twice per interval as recommended
+        if let Some(watchdog) = self.watchdog
+            && self.last_ping.elapsed() >= watchdog.checked_div(2).unwrap_or_default()
+        {
+            let stalled = heartbeat_age >= watchdog;
+            if stalled && !self.stalled {
+                // Log the stall once
+                let error =
+                    crate::error!("A worker has not responded for {heartbeat_age:?}; withholding watchdog pings");
+                crate::log!(warn: error);
+            }
+            if !stalled {
+                self.notify("WATCHDOG=1");
+                self.last_ping = Instant::now();
+            }
+            self.stalled = stalled;
+        }
+
+        // Report the amount of sessions
+        if self.last_sessions != Some(sessions) {
+            self.notify(&format!("STATUS=Forwarding {sessions} sessions"));
+            self.last_sessions = Some(sessions);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! The session table
+
+use crate::config::{Config, EvictionPolicy, TakeoverPolicy};
+use crate::error;
+use crate::error::DropReason;
+use crate::log;
+use crate::packet;
+use crate::session::Session;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// How a new session can be admitted to the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// There is a free slot for the new session
+    Free,
+    /// The session with the given client address must be evicted for the new session
+    Evict(SocketAddr),
+}
+
+/// A table of concurrent relay sessions
+///
+/// # Routing
+/// All sessions share the same relay socket, so the server sees all clients with the same relay address. Packets from a
+/// client are associated by the client address; packets from the server are associated by the WireGuard receiver index,
+/// which the client has announced as sender index in its handshake messages. Server packets without a receiver index
+/// (i.e. server-initiated handshakes) are forwarded to all sessions of that server; clients will simply drop
+/// initiations that have not been created for their public key.
+#[derive(Debug)]
+pub struct SessionTable {
+    /// The sessions by client address
+    sessions: HashMap<SocketAddr, Session>,
+    /// The client addresses by client sender index
+    indices: HashMap<u32, SocketAddr>,
+    /// The amount of sessions per server address
+    servers: HashMap<SocketAddr, usize>,
+    /// The amount of sessions per client address prefix
+    prefixes: HashMap<IpAddr, usize>,
+    /// The maximum amount of concurrent sessions
+    max_sessions: usize,
+    /// The eviction policy if the session limit has been reached
+    eviction: EvictionPolicy,
+    /// The takeover policy if the session limit has been reached
+    takeover: TakeoverPolicy,
+    /// The maximum amount of concurrent sessions per client address prefix, or `0` if unlimited
+    max_sessions_per_prefix: usize,
+    /// The IPv4 prefix length
+    prefix_v4: u8,
+    /// The IPv6 prefix length
+    prefix_v6: u8,
+    /// The time of the last expiry sweep
+    last_sweep: Instant,
+}
+impl SessionTable {
+    /// The interval between two expiry sweeps
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates a new session table
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            indices: HashMap::new(),
+            servers: HashMap::new(),
+            prefixes: HashMap::new(),
+            max_sessions: config.WGPROXY_MAX_SESSIONS,
+            eviction: config.WGPROXY_EVICTION,
+            takeover: config.WGPROXY_TAKEOVER,
+            max_sessions_per_prefix: config.WGPROXY_MAX_SESSIONS_PER_PREFIX,
+            prefix_v4: config.WGPROXY_PREFIX_V4,
+            prefix_v6: config.WGPROXY_PREFIX_V6,
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Applies the session limits of a reloaded config; existing sessions are kept even if they exceed the new limits
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.max_sessions = config.WGPROXY_MAX_SESSIONS;
+        self.eviction = config.WGPROXY_EVICTION;
+        self.takeover = config.WGPROXY_TAKEOVER;
+        self.max_sessions_per_prefix = config.WGPROXY_MAX_SESSIONS_PER_PREFIX;
+        self.prefix_v4 = config.WGPROXY_PREFIX_V4;
+        self.prefix_v6 = config.WGPROXY_PREFIX_V6;
+
+        // Recount the sessions per prefix as the prefix lengths may have changed
+        let prefixes: Vec<IpAddr> = self.sessions.keys().map(|client_address| self.prefix(client_address)).collect();
+        self.prefixes.clear();
+        for prefix in prefixes {
+            Self::increment(&mut self.prefixes, prefix);
+        }
+
+        // Apply the session timeouts and limits
+        for session in self.sessions.values_mut() {
+            session.reconfigure(config);
+        }
+    }
+
+    /// Whether the given source address belongs to a client or server of an existing session
+    pub fn is_known(&self, source: &SocketAddr) -> bool {
+        self.sessions.contains_key(source) || self.servers.contains_key(source)
+    }
+
+    /// The active sessions
+    pub fn sessions(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+
+    /// The amount of active sessions
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Removes all expired sessions if the last sweep is older than the sweep interval
+    pub fn expire(&mut self) {
+        // Rate-limit the sweeps
+        if self.last_sweep.elapsed() < Self::SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = Instant::now();
+
+        // Collect and drop all expired sessions
+        let expired: Vec<SocketAddr> = self
+            .sessions
+            .values()
+            .filter(|session| session.is_expired())
+            .map(|session| *session.client_address())
+            .collect();
+        for client_address in expired {
+            if let Some(session) = self.remove(&client_address) {
+                log!(info: error!("Dropping expired session {session}"));
+            }
+        }
+    }
+
+    /// Checks whether a new session for the given client address can be admitted
+    pub fn admit(&self, client_address: &SocketAddr) -> Result<Admission, DropReason> {
+        // Enforce the per-prefix limit
+        let prefix = self.prefix(client_address);
+        let sessions_per_prefix = self.prefixes.get(&prefix).copied().unwrap_or_default();
+        if self.max_sessions_per_prefix > 0 && sessions_per_prefix >= self.max_sessions_per_prefix {
+            return Err(DropReason::PrefixLimit(prefix));
+        }
+
+        // Check if we have a free slot
+        if self.sessions.len() < self.max_sessions {
+            return Ok(Admission::Free);
+        }
+
+        // Select a session to evict
+        let least_recently_active = |pending_only: bool| {
+            (self.sessions.values())
+                .filter(|session| !pending_only || session.is_pending())
+                .min_by_key(|session| session.last_activity())
+        };
+        let victim = match self.eviction {
+            EvictionPolicy::LeastRecentlyActive => least_recently_active(false),
+            EvictionPolicy::Pending => least_recently_active(true),
+            EvictionPolicy::Reject => None,
+        };
+        let victim = victim.or_else(|| {
+            // Fall back to the takeover policy
+            least_recently_active(false).filter(|session| session.may_takeover(self.takeover))
+        });
+
+        // Evict the victim or reject the session
+        match victim {
+            Some(victim) => Ok(Admission::Evict(*victim.client_address())),
+            None => Err(DropReason::SessionLimit),
+        }
+    }
+
+    /// Inserts a new session according to the given admission
+    pub fn insert(&mut self, session: Session, admission: Admission) {
+        // Evict the victim if necessary
+        if let Admission::Evict(victim) = admission
+            && let Some(displaced) = self.remove(&victim)
+        {
+            log!(info: error!("Session {displaced} has been taken over by {}", session.client_address()));
+        }
+
+        // Register the session and its known indices
+        for index in session.client_indices() {
+            self.indices.insert(index, *session.client_address());
+        }
+        let prefix = self.prefix(session.client_address());
+        Self::increment(&mut self.prefixes, prefix);
+        Self::increment(&mut self.servers, *session.server_address());
+        self.sessions.insert(*session.client_address(), session);
+    }
+
+    /// Routes an incoming packet to the associated session(s) and collects the destination addresses
+    pub fn forward(
+        &mut self,
+        packet: &[u8],
+        source: &SocketAddr,
+        destinations: &mut Vec<SocketAddr>,
+    ) -> Result<(), DropReason> {
+        // Forward client packets and learn their sender indices
+        if let Some(session) = self.sessions.get_mut(source) {
+            destinations.extend(session.forward(packet, source)?);
+            if let Some(index) = packet::sender_index(packet) {
+                // Register the index with the session and the index table
+                if let Some(evicted) = session.register_index(index)
+                    && self.indices.get(&evicted) == Some(source)
+                {
+                    self.indices.remove(&evicted);
+                }
+                self.indices.insert(index, *source);
+            }
+            return Ok(());
+        }
+
+        // Forward server packets by their receiver index
+        if let Some(index) = packet::receiver_index(packet) {
+            let session = (self.indices.get(&index))
+                .and_then(|client_address| self.sessions.get_mut(client_address))
+                .ok_or(DropReason::UnknownIndex { index, source: *source })?;
+            destinations.extend(session.forward(packet, source)?);
+            return Ok(());
+        }
+
+        // Forward server packets without receiver index to all sessions of that server
+        let sessions = self.sessions.values_mut().filter(|session| session.server_address().eq(source));
+        for session in sessions {
+            if let Ok(destination) = log!(warn: session.forward(packet, source)) {
+                destinations.extend(destination);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the session with the given client address
+    fn remove(&mut self, client_address: &SocketAddr) -> Option<Session> {
+        let session = self.sessions.remove(client_address)?;
+
+        // Unregister the indices if they still belong to this session
+        for index in session.client_indices() {
+            if self.indices.get(&index) == Some(client_address) {
+                self.indices.remove(&index);
+            }
+        }
+
+        // Decrement the counters
+        let prefix = self.prefix(client_address);
+        Self::decrement(&mut self.prefixes, prefix);
+        Self::decrement(&mut self.servers, *session.server_address());
+        Some(session)
+    }
+
+    /// Increments a counter
+    fn increment<K>(counters: &mut HashMap<K, usize>, key: K)
+    where
+        K: Eq + Hash,
+    {
+        let counter = counters.entry(key).or_default();
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Decrements a counter and removes it if it reaches zero
+    fn decrement<K>(counters: &mut HashMap<K, usize>, key: K)
+    where
+        K: Eq + Hash,
+    {
+        if let Entry::Occupied(mut entry) = counters.entry(key) {
+            match entry.get().saturating_sub(1) {
+                0 => drop(entry.remove()),
+                count => *entry.get_mut() = count,
+            }
+        }
+    }
+
+    /// Computes the address prefix of the given client address
+    fn prefix(&self, client_address: &SocketAddr) -> IpAddr {
+        match client_address.ip().to_canonical() {
+            IpAddr::V4(address) => {
+                let shift = 32u32.saturating_sub(u32::from(self.prefix_v4));
+                let mask = u32::MAX.checked_shl(shift).unwrap_or_default();
+                IpAddr::V4(Ipv4Addr::from_bits(address.to_bits() & mask))
+            }
+            IpAddr::V6(address) => {
+                let shift = 128u32.saturating_sub(u32::from(self.prefix_v6));
+                let mask = u128::MAX.checked_shl(shift).unwrap_or_default();
+                IpAddr::V6(Ipv6Addr::from_bits(address.to_bits() & mask))
+            }
+        }
+    }
+}
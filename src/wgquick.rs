@@ -0,0 +1,112 @@
+//! Support for wg-quick client configs
+
+use crate::error;
+use crate::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The peer of a wg-quick client config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// The base64-encoded public key of the peer
+    pub public_key: String,
+    /// The endpoint of the peer if any
+    pub endpoint: Option<String>,
+}
+impl Peer {
+    /// Loads the single `[Peer]` section of the given wg-quick config
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| error!(with: e, "Failed to read WireGuard config {}", path.display()))?;
+        Self::parse(&text).map_err(|e| error!("Invalid WireGuard config {}: {e}", path.display()))
+    }
+
+    /// Parses the single `[Peer]` section of a wg-quick config
+    ///
+    /// # Note
+    /// A relay forwards to a single server, so configs with more than one peer are rejected.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let (mut peers, mut public_key, mut endpoint) = (0usize, None, None);
+        for (line, section) in lines(text) {
+            match (section, parse_setting(line)) {
+                (Section::Peer { header: true }, _) => peers = peers.saturating_add(1),
+                (Section::Peer { .. }, Some(("PublicKey", value))) => public_key = Some(value.to_string()),
+                (Section::Peer { .. }, Some(("Endpoint", value))) => endpoint = Some(value.to_string()),
+                _ => (),
+            }
+        }
+
+        // Validate the peer
+        match (peers, public_key) {
+            (0, _) => Err(error!("Missing [Peer] section")),
+            (1, Some(public_key)) => Ok(Self { public_key, endpoint }),
+            (1, None) => Err(error!("Missing PublicKey in [Peer] section")),
+            (peers, _) => Err(error!("Expected a single [Peer] section, found {peers}")),
+        }
+    }
+}
+
+/// Rewrites the `Endpoint` of the single `[Peer]` section of a wg-quick config, and keeps everything else as-is
+pub fn rewrite_endpoint(text: &str, endpoint: &str) -> Result<String, Error> {
+    // Validate the config first
+    let peer = Peer::parse(text)?;
+
+    // Replace the endpoint line, or insert it after the section header if the config has no endpoint
+    let mut rewritten = String::new();
+    for (line, section) in lines(text) {
+        match (section, parse_setting(line)) {
+            (Section::Peer { .. }, Some(("Endpoint", _))) => {
+                let key = line.split_once('=').map_or("Endpoint ", |(key, _)| key);
+                rewritten.push_str(&format!("{key}= {endpoint}\n"));
+            }
+            (Section::Peer { header: true }, _) if peer.endpoint.is_none() => {
+                rewritten.push_str(&format!("{line}\nEndpoint = {endpoint}\n"));
+            }
+            _ => rewritten.push_str(&format!("{line}\n")),
+        }
+    }
+    Ok(rewritten)
+}
+
+/// The section of a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    /// Any section other than `[Peer]`, or no section
+    Other,
+    /// The `[Peer]` section, and whether the line is its header
+    Peer {
+        /// Whether the line is the section header
+        header: bool,
+    },
+}
+
+/// Iterates over the lines of a config together with their section
+fn lines(text: &str) -> impl Iterator<Item = (&str, Section)> {
+    let mut section = Section::Other;
+    text.lines().map(move |line| {
+        let header = strip_comment(line).trim();
+        if header.starts_with('[') {
+            section = match header.eq_ignore_ascii_case("[Peer]") {
+                true => Section::Peer { header: true },
+                false => Section::Other,
+            };
+            return (line, section);
+        }
+        if let Section::Peer { .. } = section {
+            section = Section::Peer { header: false };
+        }
+        (line, section)
+    })
+}
+
+/// Parses a `Key = Value` line and normalizes the known keys, which are case-insensitive
+fn parse_setting(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = strip_comment(line).split_once('=')?;
+    let key = ["PublicKey", "Endpoint"].into_iter().find(|known| known.eq_ignore_ascii_case(key.trim()))?;
+    Some((key, value.trim()))
+}
+
+/// Strips a trailing comment from a line
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(line, _)| line)
+}
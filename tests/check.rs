@@ -0,0 +1,107 @@
+//! Config-check-related test cases
+
+use std::collections::BTreeMap;
+use std::{env, fs, process};
+use wgproxy::config::Config;
+
+/// Creates the values from the given pairs
+fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+/// Tests that all problems are reported at once
+#[test]
+pub fn problems() {
+    let values = values(&[
+        ("WGPROXY_SERVER", "[::1]:51820"),
+        ("WGPROXY_PUBKEY", "invalid"),
+        ("WGPROXY_LISTEN", "127.0.0.1:51821"),
+        ("WGPROXY_TIMEOUT", "0"),
+        ("WGPROXY_TIMEOUT_UPLINK", "60"),
+        ("WGPROXY_WORKERS", "0"),
+        ("WGPROXY_STATE", "/nonexistent/wgproxy/state"),
+    ]);
+    let problems = Config::check(&values).expect_err("unexpected valid config");
+    let problems: Vec<_> = problems.iter().map(|problem| problem.error.as_str()).collect();
+    assert_eq!(
+        problems,
+        [
+            "WGPROXY_TIMEOUT: A timeout of zero expires every session immediately",
+            "WGPROXY_TIMEOUT_DOWNLINK: A timeout of zero expires every session immediately",
+            "WGPROXY_SERVER: Server [::1]:51820 is not reachable from listening address 127.0.0.1:51821",
+            "WGPROXY_STATE: The directory /nonexistent/wgproxy does not exist",
+            "WGPROXY_PUBKEY: Failed to base64-decode public key \"invalid\"\n caused by: invalid Base64 encoding",
+            r#"WGPROXY_WORKERS: Invalid worker count "0""#,
+        ]
+    );
+}
+
+/// Tests that an invalid timeout is not inherited as zero by the directional timeouts
+#[test]
+pub fn invalid_timeout() {
+    let values = values(&[
+        ("WGPROXY_SERVER", "127.0.0.1:51820"),
+        ("WGPROXY_PUBKEY", "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4="),
+        ("WGPROXY_TIMEOUT", "5x"),
+    ]);
+    let problems = Config::check(&values).expect_err("unexpected valid config");
+    let problems: Vec<_> = problems.iter().map(|problem| problem.error.as_str()).collect();
+    let expected = r#"WGPROXY_TIMEOUT: Invalid duration "5x"; expected e.g. "90s", "5m", "1h30m" or seconds like "90""#;
+    assert_eq!(problems, [expected]);
+}
+
+/// Tests that a valid config is returned
+#[test]
+pub fn valid() {
+    let values = values(&[
+        ("WGPROXY_SERVER", "127.0.0.1:51820"),
+        ("WGPROXY_PUBKEY", "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4="),
+        ("WGPROXY_LISTEN", "[::]:51821"),
+    ]);
+    let config = Config::check(&values).expect("unexpected invalid config");
+    assert_eq!(config.WGPROXY_SERVER, "127.0.0.1:51820");
+}
+
+/// Tests that a config without any currently valid public key is reported
+#[test]
+pub fn expired_pubkeys() {
+    let values = values(&[
+        ("WGPROXY_SERVER", "127.0.0.1:51820"),
+        ("WGPROXY_PUBKEY", "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=@..2020-01-01"),
+    ]);
+    let problems = Config::check(&values).expect_err("unexpected valid config");
+    let problems: Vec<_> = problems.iter().map(|problem| problem.error.as_str()).collect();
+    assert_eq!(problems, ["WGPROXY_PUBKEY: No public key is currently valid"]);
+}
+
+/// Tests that the problems of all relays in a config file are reported with the lines of their settings
+#[test]
+pub fn file() {
+    let path = env::temp_dir().join(format!("wgproxy-check-{}.toml", process::id()));
+    let text = r#"pubkey = "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4="
+server = "127.0.0.1:51820"
+
+[[relay]]
+listen = "127.0.0.1:51821"
+timeout = 0
+
+[[relay]]
+listen = "127.0.0.1:51821"
+workers = 0
+"#;
+    fs::write(&path, text).expect("failed to write config file");
+
+    let problems = Config::check_file(&path).expect_err("unexpected valid config file");
+    let problems: Vec<_> = problems.iter().map(|problem| problem.error.clone()).collect();
+    let prefix = format!("Invalid config file {}", path.display());
+    assert_eq!(
+        problems,
+        [
+            format!("{prefix}:6: WGPROXY_TIMEOUT: A timeout of zero expires every session immediately"),
+            format!("{prefix}:4: WGPROXY_TIMEOUT_UPLINK: A timeout of zero expires every session immediately"),
+            format!("{prefix}:4: WGPROXY_TIMEOUT_DOWNLINK: A timeout of zero expires every session immediately"),
+            format!(r#"{prefix}:10: WGPROXY_WORKERS: Invalid worker count "0""#),
+        ]
+    );
+    let _ = fs::remove_file(&path);
+}
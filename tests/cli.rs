@@ -0,0 +1,77 @@
+//! Command-line-related test cases
+//!
+//! # Note
+//! The environment and the config overrides are process-global, so these tests live in their own test binary.
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use wgproxy::cli::{self, Args, Command};
+use wgproxy::config::Config;
+
+/// Parses the given arguments
+fn parse(args: &[&str]) -> Result<Args, wgproxy::error::Error> {
+    Args::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+/// Tests that flags are mapped to their environment variables and take precedence over the environment
+#[test]
+pub fn overrides() {
+    // Safety: There are no other threads that access the environment
+    unsafe {
+        env::set_var("WGPROXY_SERVER", "127.0.0.1:51820");
+        env::set_var("WGPROXY_PUBKEY", "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=");
+        env::set_var("WGPROXY_TIMEOUT", "10");
+        env::set_var("WGPROXY_WORKERS", "2");
+    }
+
+    // Parse and apply the flags
+    let args = parse(&["--timeout", "30", "--listen=127.0.0.1:51821", "--timeout-uplink", "5"]).expect("invalid flags");
+    assert_eq!(args.command, Command::Run);
+    args.apply().expect("failed to apply flags");
+
+    // Flags must take precedence, and the environment must still apply otherwise
+    let config = Config::from_env().expect("failed to load config");
+    assert_eq!(config.WGPROXY_TIMEOUT, Duration::from_secs(30));
+    assert_eq!(config.WGPROXY_TIMEOUT_UPLINK, Duration::from_secs(5));
+    assert_eq!(config.WGPROXY_TIMEOUT_DOWNLINK, Duration::from_secs(30));
+    assert_eq!(config.WGPROXY_LISTEN, "127.0.0.1:51821".parse().expect("invalid listening address"));
+    assert_eq!(config.WGPROXY_WORKERS, 2);
+
+    // Invalid values must be reported when the config is loaded
+    parse(&["--workers", "0"]).expect("invalid flags").apply().expect("failed to apply flags");
+    let error = Config::from_env().expect_err("unexpected valid config");
+    assert_eq!(error.error, r#"Invalid worker count "0""#);
+}
+
+/// Tests that invalid arguments and informational flags are handled
+#[test]
+pub fn arguments() {
+    assert_eq!(parse(&["--help"]).expect("invalid flags").command, Command::Help);
+    assert_eq!(parse(&["--timeout", "1", "-V"]).expect("invalid flags").command, Command::Version);
+    assert_eq!(
+        parse(&["--bogus", "1"]).expect_err("unexpected valid flag").error,
+        r#"Unknown flag "--bogus"; see --help"#
+    );
+    assert_eq!(
+        parse(&["--timeout"]).expect_err("unexpected valid flag").error,
+        r#"Missing value for flag "--timeout""#
+    );
+    assert_eq!(
+        parse(&["timeout"]).expect_err("unexpected valid flag").error,
+        r#"Unexpected argument "timeout"; see --help"#
+    );
+
+    // Subcommands must take their positional arguments
+    assert_eq!(parse(&["check", "--timeout", "1"]).expect("invalid args").command, Command::Check);
+    let args =
+        parse(&["rewrite-endpoint", "wg0.conf", "--loglevel", "2", "relay.invalid:51820"]).expect("invalid args");
+    let endpoint = "relay.invalid:51820".to_string();
+    assert_eq!(args.command, Command::RewriteEndpoint { config: PathBuf::from("wg0.conf"), endpoint });
+    assert!(parse(&["rewrite-endpoint", "wg0.conf"]).is_err(), "unexpected valid subcommand");
+
+    // The help must document every flag with its environment variable and default
+    let help = cli::help();
+    assert!(help.contains("--max-sessions-per-prefix <VALUE>"), "missing flag in help");
+    assert!(help.contains("[env: WGPROXY_LISTEN] [default: [::]:51820]"), "missing default in help");
+}
@@ -0,0 +1,114 @@
+//! Config-parsing-related test cases
+
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+use wgproxy::config::{Config, PublicKey, Sandbox, TakeoverPolicy};
+
+/// Loads the config from the required values and the given pairs
+fn load(pairs: &[(&str, &str)]) -> Result<Config, wgproxy::error::Error> {
+    let mut values: BTreeMap<_, _> =
+        [("WGPROXY_SERVER", "127.0.0.1:51820"), ("WGPROXY_PUBKEY", "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=")]
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+    values.extend(pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+    Config::from_values(&values)
+}
+
+/// Tests that durations are accepted with units and as plain seconds
+#[test]
+pub fn durations() {
+    let config = load(&[
+        ("WGPROXY_TIMEOUT", "1h30m"),
+        ("WGPROXY_TIMEOUT_UPLINK", "90"),
+        ("WGPROXY_TIMEOUT_DOWNLINK", "1m500ms"),
+        ("WGPROXY_REKEY_GRACE", "30s"),
+        ("WGPROXY_REPLAY_WINDOW", "1d"),
+        ("WGPROXY_TAKEOVER", "idle:5m"),
+        ("WGPROXY_DRAIN", "0s"),
+    ])
+    .expect("failed to load config");
+    assert_eq!(config.WGPROXY_TIMEOUT, Duration::from_secs(5400));
+    assert_eq!(config.WGPROXY_TIMEOUT_UPLINK, Duration::from_secs(90));
+    assert_eq!(config.WGPROXY_TIMEOUT_DOWNLINK, Duration::from_millis(60500));
+    assert_eq!(config.WGPROXY_REKEY_GRACE, Some(Duration::from_secs(30)));
+    assert_eq!(config.WGPROXY_REPLAY_WINDOW, Duration::from_secs(86400));
+    assert_eq!(config.WGPROXY_TAKEOVER, TakeoverPolicy::Idle(Duration::from_secs(300)));
+    assert_eq!(config.WGPROXY_DRAIN, Duration::ZERO);
+
+    // Invalid durations must list the accepted forms
+    for duration in ["", "5x", "m", "1.5h", "-5s", "5 m"] {
+        let error = load(&[("WGPROXY_TIMEOUT", duration)]).expect_err("unexpected valid duration");
+        let expected =
+            format!(r#"Invalid duration "{duration}"; expected e.g. "90s", "5m", "1h30m" or seconds like "90""#);
+        assert_eq!(error.error, expected);
+    }
+}
+
+/// Tests that log levels are accepted by name and as integer
+#[test]
+pub fn loglevels() {
+    let levels = [("error", 0), ("warn", 1), ("info", 2), ("debug", 3), ("trace", 3), ("2", 2)];
+    for (name, level) in levels {
+        let config = load(&[("WGPROXY_LOGLEVEL", name)]).expect("failed to load config");
+        assert_eq!(config.WGPROXY_LOGLEVEL, level);
+    }
+
+    // Invalid log levels must list the accepted forms
+    let error = load(&[("WGPROXY_LOGLEVEL", "verbose")]).expect_err("unexpected valid log level");
+    assert_eq!(error.error, r#"Invalid log level "verbose"; expected error, warn, info, debug, trace or 0 to 3"#);
+}
+
+/// Tests that the sandbox kills the process by default and only lets syscalls fail if requested explicitly
+#[test]
+pub fn sandbox_modes() {
+    let modes = [("false", Sandbox::Off), ("true", Sandbox::Kill), ("kill", Sandbox::Kill), ("errno", Sandbox::Errno)];
+    for (name, mode) in modes {
+        let config = load(&[("WGPROXY_SANDBOX", name)]).expect("failed to load config");
+        assert_eq!(config.WGPROXY_SANDBOX, mode);
+    }
+
+    // Invalid modes must list the accepted forms
+    let error = load(&[("WGPROXY_SANDBOX", "log")]).expect_err("unexpected valid sandbox mode");
+    assert_eq!(error.error, r#"Invalid sandbox mode "log"; expected true, kill, errno or false"#);
+}
+
+/// Tests that public keys are accepted with optional validity periods
+#[test]
+pub fn pubkeys() {
+    let pubkeys = "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=@..2026-11-08, \
+        QkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkI=@2026-11-01T12:30:00Z..1893456000";
+    let config = load(&[("WGPROXY_PUBKEY", pubkeys)]).expect("failed to load config");
+    let (old, new) = (config.WGPROXY_PUBKEY[0], config.WGPROXY_PUBKEY[1]);
+    assert_eq!(&old.key, b"Karinmagen Karinmagen Karinmagen");
+    assert_eq!(old.not_before, None);
+    assert_eq!(old.not_after, Some(UNIX_EPOCH + Duration::from_secs(1794096000)));
+    assert_eq!(new.key, [0x42; 32]);
+    assert_eq!(new.not_before, Some(UNIX_EPOCH + Duration::from_secs(1793536200)));
+    assert_eq!(new.not_after, Some(UNIX_EPOCH + Duration::from_secs(1893456000)));
+
+    // The validity periods must be kept when displayed
+    assert_eq!(old.to_string(), "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=@..2026-11-08T00:00:00Z");
+    assert_eq!(
+        new.to_string(),
+        "QkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkI=@2026-11-01T12:30:00Z..2030-01-01T00:00:00Z"
+    );
+    assert_eq!(new.to_string().parse::<PublicKey>().expect("failed to parse public key"), new);
+
+    // The validity periods must be well-formed
+    let pubkey = "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=";
+    let error = load(&[("WGPROXY_PUBKEY", &format!("{pubkey}@2026-11-01"))]).expect_err("unexpected valid pubkey");
+    assert_eq!(error.error, r#"Invalid validity period "2026-11-01"; expected "<not-before>..<not-after>""#);
+    let error = load(&[("WGPROXY_PUBKEY", &format!("{pubkey}@2026-02-30.."))]).expect_err("unexpected valid pubkey");
+    let expected = concat!(
+        r#"Invalid timestamp "2026-02-30"; expected e.g. "2026-11-01T00:00:00Z", "2026-11-01" "#,
+        r#"or Unix seconds like "1793491200""#
+    );
+    assert_eq!(error.error, expected);
+    let error =
+        load(&[("WGPROXY_PUBKEY", &format!("{pubkey}@2026-11-08..2026-11-01"))]).expect_err("unexpected valid pubkey");
+    assert_eq!(
+        error.error,
+        r#"Invalid validity period "2026-11-08..2026-11-01"; the key expires before it becomes valid"#
+    );
+}
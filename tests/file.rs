@@ -0,0 +1,148 @@
+//! Config-file-related test cases
+//!
+//! # Note
+//! Termination requests are process-global, so these tests live in their own test binary.
+
+mod utils;
+use base64ct::{Base64, Encoding};
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs, process, thread};
+use wgproxy::config::Config;
+
+/// Writes the given config file to a unique temporary path
+fn write_config(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("wgproxy-file-{name}-{}.toml", process::id()));
+    fs::write(&path, text).expect("failed to write config file");
+    path
+}
+
+/// Tests that all relays of a config file run in one process and enforce their own access control lists
+#[test]
+pub fn relays() {
+    let server0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create server socket");
+    let server1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create server socket");
+    server1.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Create a config file with two relays and a unique port range for this test file
+    let pubkey = Base64::encode_string(&utils::WGPROXY_PUBKEY);
+    let text = format!(
+        r#"# Global settings
+pubkey = "{pubkey}"
+timeout = 3
+drain = 0
+
+[[relay]]
+listen = "127.0.0.1:64500"
+server = "{}"
+
+[[relay]]
+listen = '127.0.0.1:64501'  # This relay denies all local clients
+server = "{}"
+deny = ["127.0.0.0/8", "::1"]
+"#,
+        server0.local_addr().expect("failed to get server socket address"),
+        server1.local_addr().expect("failed to get server socket address"),
+    );
+    let path = write_config("relays", &text);
+
+    // Both relays must share the global settings
+    let configs = Config::from_file(&path).expect("failed to load config file");
+    assert_eq!(configs.len(), 2);
+    assert!(configs.iter().all(|config| config.WGPROXY_TIMEOUT == Duration::from_secs(3)));
+    assert_eq!(configs[1].WGPROXY_DENY.len(), 2);
+
+    // Boot the relays
+    let path_ = path.clone();
+    let eventloop = thread::spawn(move || wgproxy::eventloop_file(&path_));
+    thread::sleep(Duration::from_secs(3));
+
+    // The first relay must forward the handshake
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&utils::WGPROXY_PUBKEY);
+    let mut buf = [0; 512];
+    client.send_to(&handshake, "127.0.0.1:64500").expect("failed to send test packet");
+    let (buf_len, _) = server0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // The second relay must drop the handshake of a denied client
+    let handshake = utils::handshake(&utils::WGPROXY_PUBKEY);
+    client.send_to(&handshake, "127.0.0.1:64501").expect("failed to send test packet");
+    server1.recv_from(&mut buf).expect_err("unexpected forwarded handshake");
+
+    // The relays must exit gracefully
+    wgproxy::signal::terminate();
+    let result = eventloop.join().expect("eventloop has panicked");
+    assert!(result.is_ok(), "eventloop has failed");
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that invalid config files are rejected with the offending line
+#[test]
+pub fn errors() {
+    let pubkey = Base64::encode_string(&utils::WGPROXY_PUBKEY);
+    let cases = [
+        ("unknown", "[[relay]]\nserver = \"127.0.0.1:51820\"\nbogus = 1\n", ":3: Unknown setting \"bogus\""),
+        ("global", "[[relay]]\nloglevel = 2\n", ":2: \"WGPROXY_LOGLEVEL\" applies to all relays"),
+        ("duplicate", "timeout = 1\ntimeout = 2\n", ":2: Duplicate setting \"WGPROXY_TIMEOUT\""),
+        ("string", "server = 127.0.0.1\n", ":1: Invalid value \"127.0.0.1\"; strings must be quoted"),
+        ("table", "[relay]\n", ":1: Unsupported table \"[relay]\""),
+        ("dotted", "relay.timeout = 1\n", ":1: Unsupported dotted key \"relay.timeout\""),
+        ("inline", "server = { host = \"127.0.0.1\" }\n", ":1: Unsupported inline table"),
+        ("multiline", "server = \"\"\"\n127.0.0.1\n\"\"\"\n", ":1: Unsupported multi-line string"),
+        ("empty", "timeout = 1\n", ": No [[relay]] tables defined"),
+        (
+            "listen",
+            &format!(
+                "pubkey = \"{pubkey}\"\nserver = \"127.0.0.1:51820\"\n\n[[relay]]\nlisten = \"127.0.0.1:51821\"\n\n\
+                 [[relay]]\nlisten = \"127.0.0.1:51821\"\n"
+            ),
+            ":8: Duplicate listening address",
+        ),
+        (
+            "value",
+            &format!("pubkey = \"{pubkey}\"\nserver = \"127.0.0.1:51820\"\n\n[[relay]]\nworkers = 0\n"),
+            ":5: Invalid worker count \"0\"",
+        ),
+    ];
+
+    for (name, text, expected) in cases {
+        let path = write_config(name, text);
+        let error = Config::from_file(&path).expect_err("unexpected valid config file");
+        let expected = format!("Invalid config file {}{expected}", path.display());
+        assert!(error.error.starts_with(&expected), "unexpected error: {}", error.error);
+        let _ = fs::remove_file(&path);
+    }
+}
+
+/// Tests that the problems of settings in the global table are reported once with their own line
+#[test]
+pub fn global_errors() {
+    let pubkey = Base64::encode_string(&utils::WGPROXY_PUBKEY);
+    let text = format!(
+        "pubkey = \"{pubkey}\"\nserver = \"127.0.0.1:51820\"\nworkers = 0\n\n[[relay]]\nlisten = \"127.0.0.1:51821\"\n\n\
+         [[relay]]\nlisten = \"127.0.0.1:51822\"\n"
+    );
+    let path = write_config("global-errors", &text);
+    let error = Config::from_file(&path).expect_err("unexpected valid config file");
+    assert_eq!(error.error, format!(r#"Invalid config file {}:3: Invalid worker count "0""#, path.display()));
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that the problems of all lines and relays are reported together
+#[test]
+pub fn all_errors() {
+    let text = "bogus = 1\ntimeout = 1\ntimeout = 2\n\n[[relay]]\nloglevel = 2\n";
+    let path = write_config("all", text);
+    let error = Config::from_file(&path).expect_err("unexpected valid config file");
+    let path_ = path.display();
+    let expected = [
+        format!(r#"Invalid config file {path_}:1: Unknown setting "bogus""#),
+        format!(r#"Invalid config file {path_}:3: Duplicate setting "WGPROXY_TIMEOUT""#),
+        format!(r#"Invalid config file {path_}:6: "WGPROXY_LOGLEVEL" applies to all relays and must be set in the "#)
+            + "global table",
+    ];
+    assert_eq!(error.error, expected.join("\n"));
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,86 @@
+//! Handover-related test cases
+
+mod utils;
+use std::net::UdpSocket;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::UnixListener;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+/// Tests that a new relay takes over the sockets and sessions of a running relay
+#[test]
+#[cfg(target_os = "linux")]
+pub fn handover() {
+    let path = env::temp_dir().join(format!("wgproxy-handover-{}", process::id()));
+    let _ = fs::remove_file(&path);
+
+    // Create custom proxy config with a unique port for this test file
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:63000".parse().expect("invalid listening address");
+        config.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_HANDOVER = Some(path.clone());
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Boot the first relay
+    let config_ = config.clone();
+    let previous = thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client0.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let mut buf = [0; 512];
+
+    // Do handshake
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client0.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Boot the second relay, which takes over from the first one
+    let config_ = config.clone();
+    thread::spawn(move || wgproxy::eventloop(config_));
+    let result = previous.join().expect("previous eventloop has panicked");
+    assert!(result.is_ok(), "previous eventloop has failed");
+
+    // The taken over session must still be forwarded, and must reject the replayed handshake
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+    client1.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected replayed handshake");
+
+    // New sessions must be accepted
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake1);
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that the takeover fails instead of hanging if the running process does not respond
+#[test]
+#[cfg(target_os = "linux")]
+pub fn unresponsive() {
+    let path = env::temp_dir().join(format!("wgproxy-handover-unresponsive-{}", process::id()));
+    let _ = fs::remove_file(&path);
+
+    // Create custom proxy config with a unique port for this test file
+    let (config, _, _) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:63001".parse().expect("invalid listening address");
+        config.WGPROXY_HANDOVER = Some(path.clone());
+    });
+
+    // Listen on the handover socket without ever sending anything
+    let _listener = UnixListener::bind(&path).expect("failed to bind handover socket");
+    let started = Instant::now();
+    let error = wgproxy::eventloop(config).expect_err("unexpected takeover from unresponsive process");
+    assert!(error.error.contains("Timed out"), "unexpected error: {}", error.error);
+    assert!(started.elapsed() < Duration::from_secs(30), "takeover has not timed out in time");
+    let _ = fs::remove_file(&path);
+}
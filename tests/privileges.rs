@@ -0,0 +1,72 @@
+//! Privilege-related test cases
+//!
+//! # Note
+//! Privileges are process-global, so these tests live in their own test binary.
+
+mod utils;
+use std::net::UdpSocket;
+use std::os::unix::fs::MetadataExt;
+use std::time::Duration;
+use std::{fs, thread};
+
+/// Tests that the relay drops its privileges and still forwards packets
+#[test]
+#[cfg(target_os = "linux")]
+pub fn drop_privileges() {
+    // Dropping privileges is only possible as root
+    let metadata = fs::metadata("/proc/self").expect("failed to get process metadata");
+    if metadata.uid() != 0 {
+        eprintln!("skipping privilege test as we are not root");
+        return;
+    }
+
+    // Create custom proxy config with a unique port for this test file and boot the relay
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:65000".parse().expect("invalid listening address");
+        config.WGPROXY_USER = Some("65534".to_string());
+        config.WGPROXY_GROUP = Some("65534".to_string());
+    });
+    let config_ = config.clone();
+    thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Ensure that the process has switched its user and group
+    let status = fs::read_to_string("/proc/self/status").expect("failed to read process status");
+    assert!(
+        status.lines().any(|line| line.starts_with("Uid:\t65534\t65534\t65534\t65534")),
+        "user has not been switched"
+    );
+    assert!(
+        status.lines().any(|line| line.starts_with("Gid:\t65534\t65534\t65534\t65534")),
+        "group has not been switched"
+    );
+    assert!(status.lines().any(|line| line.trim_end() == "Groups:"), "supplementary groups have not been cleared");
+
+    // Do handshake
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+}
+
+/// Tests that a numeric user without passwd entry requires an explicit group
+#[test]
+#[cfg(target_os = "linux")]
+pub fn unknown_user() {
+    // Ensure that the user has no passwd entry
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    if passwd.lines().any(|line| line.split(':').nth(2) == Some("64999")) {
+        eprintln!("skipping unknown user test as the user exists");
+        return;
+    }
+
+    // Create custom proxy config with a unique port for this test file
+    let (config, _, _) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:65001".parse().expect("invalid listening address");
+        config.WGPROXY_USER = Some("64999".to_string());
+    });
+    let error = wgproxy::eventloop(config).expect_err("unexpected privilege drop without group");
+    assert_eq!(error.error, r#"User "64999" has no passwd entry, so WGPROXY_GROUP must be set"#);
+}
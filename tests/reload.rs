@@ -0,0 +1,70 @@
+//! Reload-related test cases
+//!
+//! # Note
+//! Reload requests and the environment are process-global, so these tests live in their own test binary.
+
+mod utils;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+/// The public key after the reload
+const WGPROXY_PUBKEY_RELOADED: [u8; 32] = [0x42; 32];
+/// The base64 encoding of [`WGPROXY_PUBKEY_RELOADED`]
+const WGPROXY_PUBKEY_RELOADED_BASE64: &str = "QkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkI=";
+
+/// Tests that a reload applies a new public key while keeping existing sessions intact
+#[test]
+pub fn reload() {
+    // Create custom proxy config with a unique port for this test file
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:62000".parse().expect("invalid listening address");
+        config.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config.WGPROXY_MAX_SESSIONS = 4;
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Boot the relay
+    let config_ = config.clone();
+    thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client0.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let mut buf = [0; 512];
+
+    // Do handshake with the initial key, and ensure that the new key is rejected
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client0.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+    client1.send_to(&utils::handshake(&WGPROXY_PUBKEY_RELOADED), wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake with new key");
+
+    // Reload the config with the new key
+    // Safety: The relay only reads the environment after the reload request below
+    unsafe {
+        std::env::set_var("WGPROXY_SERVER", &config.WGPROXY_SERVER);
+        std::env::set_var("WGPROXY_PUBKEY", WGPROXY_PUBKEY_RELOADED_BASE64);
+        std::env::set_var("WGPROXY_LISTEN", config.WGPROXY_LISTEN.to_string());
+        std::env::set_var("WGPROXY_TIMEOUT", "30");
+        std::env::set_var("WGPROXY_MAX_SESSIONS", "4");
+    }
+    wgproxy::signal::reload();
+    thread::sleep(Duration::from_secs(1));
+
+    // The new key must be accepted now
+    let handshake1 = utils::handshake(&WGPROXY_PUBKEY_RELOADED);
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake1);
+
+    // The existing session must still be forwarded
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+}
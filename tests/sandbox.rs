@@ -0,0 +1,97 @@
+//! Sandbox-related test cases
+//!
+//! # Note
+//! Termination requests are process-global, so these tests live in their own test binary.
+
+mod utils;
+use base64ct::{Base64, Encoding};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use std::{env, fs, process, thread};
+use wgproxy::config::Sandbox;
+
+/// Tests that the sandboxed relay forwards packets, resolves a server hostname after a reload, writes its state and exits
+/// gracefully
+#[test]
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn sandbox() {
+    let dir = env::temp_dir().join(format!("wgproxy-sandbox-{}", process::id()));
+    fs::create_dir_all(&dir).expect("failed to create state directory");
+    let path = dir.join("state");
+
+    // Create custom proxy config with a unique port for this test file
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:65500".parse().expect("invalid listening address");
+        config.WGPROXY_STATE = Some(path.clone());
+        config.WGPROXY_STATE_INTERVAL = Duration::from_secs(1);
+        config.WGPROXY_DRAIN = Duration::ZERO;
+        config.WGPROXY_SANDBOX = Sandbox::Kill;
+    });
+
+    // Boot the relay
+    let config_ = config.clone();
+    let eventloop = thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Ensure that the relay threads are running in seccomp filter mode
+    let tasks = fs::read_dir("/proc/self/task").expect("failed to list threads");
+    let sandboxed = tasks.flatten().any(|task| {
+        let status = fs::read_to_string(task.path().join("status")).unwrap_or_default();
+        status.lines().any(|line| line == "Seccomp:\t2")
+    });
+    assert!(sandboxed, "relay is not sandboxed");
+
+    // Do handshake
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Do reply
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test reply");
+    assert_eq!(&buf[..buf_len], response);
+
+    // Reload with a server hostname, so that the DNS resolution and its NSS modules run within the sandbox; a syscall that
+    //  is missing from the allowlist kills the test process
+    //  (the server must listen on the first resolved address, as the relay forwards to that one)
+    let mut server_addresses = ("localhost", 0).to_socket_addrs().expect("failed to resolve localhost");
+    let server_address = server_addresses.next().expect("failed to resolve localhost");
+    let server1 = UdpSocket::bind(server_address).expect("failed to create server socket");
+    server1.set_read_timeout(Some(Duration::from_secs(5))).expect("failed to set server read timeout");
+    let server1_port = server1.local_addr().expect("failed to get server socket address").port();
+    // Safety: The relay only reads the environment after the reload request below
+    unsafe {
+        env::set_var("WGPROXY_SERVER", format!("localhost:{server1_port}"));
+        env::set_var("WGPROXY_PUBKEY", Base64::encode_string(&config.WGPROXY_PUBKEY[0].key));
+        env::set_var("WGPROXY_LISTEN", config.WGPROXY_LISTEN.to_string());
+        env::set_var("WGPROXY_MAX_SESSIONS", "2");
+        env::set_var("WGPROXY_STATE", &path);
+        env::set_var("WGPROXY_STATE_INTERVAL", "1");
+        env::set_var("WGPROXY_SANDBOX", "true");
+        env::set_var("WGPROXY_DRAIN", "0");
+    }
+    wgproxy::signal::reload();
+    thread::sleep(Duration::from_secs(1));
+
+    // A new session must be forwarded to the resolved server
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake1);
+
+    // Wait for a snapshot, and ensure that it has been written within the sandbox
+    thread::sleep(Duration::from_secs(2));
+    let state = fs::read_to_string(&path).expect("failed to read state file");
+    assert!(state.starts_with("wgproxy-state"), "invalid state file");
+
+    // The relay must exit gracefully
+    wgproxy::signal::terminate();
+    let result = eventloop.join().expect("eventloop has panicked");
+    assert!(result.is_ok(), "eventloop has failed");
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,47 @@
+//! Secret-file-related test cases
+//!
+//! # Note
+//! The environment is process-global, so these tests live in their own test binary.
+
+use std::time::Duration;
+use std::{env, fs, process};
+use wgproxy::config::Config;
+
+/// Tests that `_FILE` variables are read and trimmed, and that they conflict with their plain variables
+#[test]
+pub fn secrets() {
+    let dir = env::temp_dir().join(format!("wgproxy-secrets-{}", process::id()));
+    fs::create_dir_all(&dir).expect("failed to create secrets directory");
+    let pubkey_path = dir.join("pubkey");
+    let timeout_path = dir.join("timeout");
+    fs::write(&pubkey_path, "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=\n").expect("failed to write secret");
+    fs::write(&timeout_path, " 42\n").expect("failed to write secret");
+
+    // Safety: There are no other threads that access the environment
+    unsafe {
+        env::set_var("WGPROXY_SERVER", "127.0.0.1:51820");
+        env::set_var("WGPROXY_PUBKEY_FILE", &pubkey_path);
+        env::set_var("WGPROXY_TIMEOUT_FILE", &timeout_path);
+    }
+
+    // The file contents must be used as values
+    let config = Config::from_env().expect("failed to load config");
+    assert_eq!(&config.WGPROXY_PUBKEY[0].key, b"Karinmagen Karinmagen Karinmagen");
+    assert_eq!(config.WGPROXY_TIMEOUT, Duration::from_secs(42));
+
+    // Both forms must not be set at the same time
+    // Safety: There are no other threads that access the environment
+    unsafe { env::set_var("WGPROXY_TIMEOUT", "10") };
+    let error = Config::from_env().expect_err("unexpected valid config");
+    assert_eq!(error.error, r#"Only one of "WGPROXY_TIMEOUT" and "WGPROXY_TIMEOUT_FILE" may be set"#);
+
+    // Missing files must be reported
+    // Safety: There are no other threads that access the environment
+    unsafe {
+        env::remove_var("WGPROXY_TIMEOUT");
+        env::set_var("WGPROXY_TIMEOUT_FILE", dir.join("missing"));
+    }
+    let error = Config::from_env().expect_err("unexpected valid config");
+    assert!(error.error.starts_with(r#"Failed to read "WGPROXY_TIMEOUT_FILE""#), "unexpected error: {}", error.error);
+    let _ = fs::remove_dir_all(&dir);
+}
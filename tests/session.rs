@@ -2,7 +2,9 @@
 
 mod utils;
 use std::net::UdpSocket;
-use std::thread;
+use std::time::{Duration, SystemTime};
+use std::{env, fs, process, thread};
+use wgproxy::config::{Backend, IdlePolicy, PublicKey, TakeoverPolicy};
 
 /// Tests that a trivial handshake and subsequent session works
 #[test]
@@ -12,7 +14,7 @@ pub fn handshake() {
 
     // Setup client
     let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
-    let handshake = utils::handshake(&config.WGPROXY_PUBKEY);
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
     let mut buf = [0; 512];
 
     // Do handshake
@@ -35,8 +37,8 @@ pub fn handshake2() {
     // Setup client
     let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
     let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
-    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY);
-    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY);
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
     let mut buf = [0; 512];
 
     // Send packet to the server
@@ -66,8 +68,8 @@ pub fn timeout() {
 
     // Setup client
     let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
-    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY);
-    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY);
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
     let mut buf = [0; 512];
 
     // Do handshake
@@ -99,8 +101,8 @@ pub fn timeout2() {
     // Setup client
     let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
     let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
-    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY);
-    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY);
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
     let mut buf = [0; 512];
 
     // Do handshake
@@ -131,7 +133,7 @@ pub fn batch() {
 
     // Setup client
     let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
-    let handshake = utils::handshake(&config.WGPROXY_PUBKEY);
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
     let mut buf = [0; 512];
 
     // Do handshake
@@ -154,3 +156,571 @@ pub fn batch() {
         assert_eq!(&buf[..buf_len], &message);
     }
 }
+
+/// Tests that the amplification guard limits downlink traffic until the client has demonstrated reachability
+#[test]
+pub fn amplification() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_AMPLIFICATION_LIMIT = 2);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Send a handshake response and some more packets back to the client
+    let response = utils::response(&handshake, b"testolope:0");
+    server.send_to(&response, relay_nat_address).expect("failed to send test reply");
+    server.send_to(b"testolope:1", relay_nat_address).expect("failed to send test reply");
+    server.send_to(b"testolope:2", relay_nat_address).expect("failed to send test reply");
+
+    // Ensure that only the first two packets arrive
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:1");
+    client.recv_from(&mut buf).expect_err("unexpected packet from unverified session");
+
+    // Demonstrate reachability with a transport packet for the handshake response
+    let transport = utils::client_transport(&response, b"testolope:3");
+    client.send_to(&transport, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], transport);
+
+    // Ensure that packets arrive again
+    server.send_to(b"testolope:4", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:4");
+}
+
+/// Tests that a transport packet which is not addressed to the forwarded handshake response does not lift the
+/// amplification guard
+#[test]
+pub fn amplification_spoofed() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_AMPLIFICATION_LIMIT = 2);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake and exhaust the amplification budget
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+    let response = utils::response(&handshake, b"testolope:0");
+    server.send_to(&response, relay_nat_address).expect("failed to send test reply");
+    server.send_to(b"testolope:1", relay_nat_address).expect("failed to send test reply");
+    client.recv_from(&mut buf).expect("failed to receive test packet");
+    client.recv_from(&mut buf).expect("failed to receive test packet");
+
+    // Send a blind transport packet with a wrong receiver index, as a spoofer that has not seen the response would
+    client.send_to(b"\x04\x00\x00\x00\x58\x58\x58\x58testolope:2", wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"\x04\x00\x00\x00\x58\x58\x58\x58testolope:2");
+
+    // Ensure that the guard is still active
+    server.send_to(b"testolope:3", relay_nat_address).expect("failed to send test reply");
+    client.recv_from(&mut buf).expect_err("unexpected packet from unverified session");
+}
+
+/// Tests that a handshake from a new client takes over an existing session if the policy allows it
+#[test]
+pub fn takeover() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_TAKEOVER = TakeoverPolicy::Always);
+
+    // Setup client
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+
+    // Do another handshake from the new address
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake1);
+
+    // Send a packet back to the client and ensure that it arrives on the new address
+    server.send_to(b"testolope:0", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:0");
+}
+
+/// Tests that a handshake from a new client only takes over an existing session after the client has been idle
+#[test]
+pub fn takeover_idle() {
+    // Start custom proxy session for testing
+    let idle = TakeoverPolicy::Idle(Duration::from_secs(1));
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_TAKEOVER = idle);
+
+    // Setup client
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake2 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+
+    // Do another handshake from the new address and ensure that the session is not taken over yet
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    server.send_to(b"testolope:0", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:0");
+
+    // Let the first client idle, then do another handshake from the new address
+    thread::sleep(Duration::from_secs(2));
+    client1.send_to(&handshake2, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake2);
+
+    // Send a packet back to the client and ensure that it arrives on the new address
+    server.send_to(b"testolope:1", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:1");
+}
+
+/// Tests that one-way traffic keeps a session alive with the uplink idle policy
+#[test]
+pub fn idle_uplink() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_IDLE_POLICY = IdlePolicy::Uplink);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Send uplink-only traffic beyond the downlink timeout
+    for _ in 0..6 {
+        thread::sleep(config.WGPROXY_TIMEOUT / 3);
+        client.send_to(b"testolope:0", wgproxy).expect("failed to send test packet");
+        let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], b"testolope:0");
+    }
+
+    // Send a packet back to the client and ensure that the session is still alive
+    server.send_to(b"testolope:1", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"testolope:1");
+}
+
+/// Tests that multiple concurrent sessions are routed by their receiver index
+#[test]
+pub fn multiple() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_MAX_SESSIONS = 2);
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client2 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake2 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshakes
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake1);
+
+    // Ensure that the session limit is enforced
+    client2.send_to(&handshake2, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake beyond the session limit");
+
+    // Send packets back to the clients and ensure that they arrive at the correct clients
+    let reply1 = utils::transport(&handshake1, b"testolope:1");
+    server.send_to(&reply1, relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], reply1);
+    let reply0 = utils::transport(&handshake0, b"testolope:0");
+    server.send_to(&reply0, relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], reply0);
+}
+
+/// Tests that the per-prefix session limit is enforced
+#[test]
+pub fn prefix_limit() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_MAX_SESSIONS_PER_PREFIX = 1;
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+
+    // Ensure that a second session from the same prefix is rejected
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake beyond the prefix session limit");
+}
+
+/// Tests that handshakes for any configured public key are accepted, and that the access control lists are enforced
+#[test]
+pub fn access_control() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        config.WGPROXY_PUBKEY.insert(0, PublicKey::new([0x42; 32]));
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_ALLOW = vec!["127.0.0.0/8".parse().expect("invalid prefix")];
+        config.WGPROXY_DENY = vec!["127.0.0.2".parse().expect("invalid prefix")];
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.2:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[1].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake with the second public key
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+
+    // Ensure that a denied client is rejected
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake from denied client");
+}
+
+/// Tests that handshakes are only accepted for public keys within their validity period
+#[test]
+pub fn key_rotation() {
+    // Start custom proxy session for testing with an expired, a current and a future public key
+    let now = SystemTime::now();
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        let expired = PublicKey { not_after: Some(now - Duration::from_secs(60)), ..PublicKey::new([0x42; 32]) };
+        let future = PublicKey { not_before: Some(now + Duration::from_secs(3600)), ..PublicKey::new([0x43; 32]) };
+        config.WGPROXY_PUBKEY[0].not_after = Some(now + Duration::from_secs(3600));
+        config.WGPROXY_PUBKEY.extend([expired, future]);
+        config.WGPROXY_MAX_SESSIONS = 4;
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client2 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake0 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[1].key);
+    let handshake2 = utils::handshake(&config.WGPROXY_PUBKEY[2].key);
+    let mut buf = [0; 512];
+
+    // Ensure that handshakes for the expired and the future public key are rejected
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake for expired public key");
+    client2.send_to(&handshake2, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake for future public key");
+
+    // Do handshake with the current public key
+    client0.send_to(&handshake0, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake0);
+}
+
+/// Tests that replayed handshakes are rejected within the replay window and accepted afterwards
+#[test]
+pub fn replay_window() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_REPLAY_WINDOW = Duration::from_secs(1);
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client2 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client0.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Ensure that a replay from another address is rejected
+    client1.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected replayed handshake");
+
+    // Ensure that the replay is accepted once the window has passed
+    thread::sleep(Duration::from_secs(2));
+    client2.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+}
+
+/// Tests that the replay cache and the sessions are restored from the state file
+#[test]
+pub fn state() {
+    let path = env::temp_dir().join(format!("wgproxy-state-{}", process::id()));
+    let _ = fs::remove_file(&path);
+
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        config.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_STATE = Some(path.clone());
+        config.WGPROXY_STATE_INTERVAL = Duration::from_secs(1);
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake and wait for the next snapshot
+    client0.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+    thread::sleep(Duration::from_secs(2));
+
+    // Start a new relay for the same server with the same state file, which must reject the replayed handshake
+    let (_, wgproxy, _) = utils::session_with(|config_| {
+        config_.WGPROXY_SERVER = config.WGPROXY_SERVER.clone();
+        config_.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config_.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config_.WGPROXY_MAX_SESSIONS = 4;
+        config_.WGPROXY_STATE = Some(path.clone());
+    });
+    client1.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected replayed handshake");
+
+    // Ensure that the restored session still routes server packets to the client
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    client0.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that a session without a re-key expires after `REJECT_AFTER_TIME` plus the grace period, and that a completed
+/// re-key keeps a session alive
+#[test]
+pub fn rekey() {
+    let path = env::temp_dir().join(format!("wgproxy-rekey-{}", process::id()));
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client0.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    client1.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let mut buf = [0; 512];
+
+    // Start custom proxy session with two restored sessions whose last handshake is 10s short of being overdue
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("invalid system time");
+        let mut state = format!("wgproxy-state 2\ntime {}\n", time.as_millis());
+        for (client, index) in [(&client0, "a0a0a0a0"), (&client1, "b0b0b0b0")] {
+            let client_address = client.local_addr().expect("failed to get client socket address");
+            let server = &config.WGPROXY_SERVER;
+            state.push_str(&format!("session {client_address} {server} 0 0 180000 true - true {index}\n"));
+        }
+        fs::write(&path, state).expect("failed to write state file");
+
+        config.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config.WGPROXY_REKEY_GRACE = Some(Duration::from_secs(10));
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_STATE = Some(path.clone());
+    });
+
+    // Re-key the second session
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client1.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+
+    // Ensure that the first session is still forwarded before its re-key is overdue
+    let transport0 = b"\x04\x00\x00\x00\xa0\xa0\xa0\xa0testolope:0";
+    server.send_to(transport0, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], transport0);
+
+    // Wait until the re-key of the first session is overdue; only the re-keyed session must be forwarded
+    thread::sleep(Duration::from_secs(9));
+    server.send_to(transport0, wgproxy).expect("failed to send test reply");
+    client0.recv_from(&mut buf).expect_err("unexpected packet from session without re-key");
+    let transport1 = utils::transport(&handshake, b"testolope:1");
+    server.send_to(&transport1, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client1.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], transport1);
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that a trivial handshake and subsequent session works with the portable backend
+#[test]
+pub fn portable() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_BACKEND = Backend::Portable);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Send packet back to the client
+    server.send_to(b"TESTOLOPE", relay_nat_address).expect("failed to send test reply");
+    let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], b"TESTOLOPE");
+}
+
+/// Tests that the io_uring backend forwards packets in both directions
+#[test]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub fn io_uring() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_BACKEND = Backend::IoUring);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Send packets back and forth
+    for _ in 0..64 {
+        server.send_to(b"TESTOLOPE", relay_nat_address).expect("failed to send test reply");
+        let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], b"TESTOLOPE");
+
+        client.send_to(b"TESTOLOPE", wgproxy).expect("failed to send test packet");
+        let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], b"TESTOLOPE");
+    }
+}
+
+/// Tests that multiple worker threads share the session table
+///
+/// # Note
+/// This only tests correctness; the workers lock the shared relay state for routing only, so that their I/O runs in
+/// parallel (see `backend::run`).
+#[test]
+#[cfg(target_os = "linux")]
+pub fn workers() {
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| {
+        config.WGPROXY_WORKERS = 4;
+        config.WGPROXY_MAX_SESSIONS = 8;
+    });
+
+    // Setup clients; their different source ports are distributed across the workers
+    let clients: Vec<_> =
+        (0..8).map(|_| UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket")).collect();
+    let mut buf = [0; 512];
+
+    // Do a handshake and reply exchange for each client
+    for client in &clients {
+        let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+        client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+        let (buf_len, relay_nat_address) = server.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], handshake);
+        assert_eq!(relay_nat_address, wgproxy);
+
+        // Send a response back to the client
+        let response = utils::response(&handshake, b"TESTOLOPE");
+        server.send_to(&response, relay_nat_address).expect("failed to send test reply");
+        let (buf_len, _) = client.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], response);
+    }
+}
+
+/// Tests that coalesced super-packets are forwarded correctly with GRO/GSO offloading
+#[test]
+#[cfg(target_os = "linux")]
+pub fn offload() {
+    use std::os::fd::AsRawFd;
+
+    // Start custom proxy session for testing
+    let (config, wgproxy, server) = utils::session_with(|config| config.WGPROXY_OFFLOAD = true);
+
+    // Setup client
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+
+    // Do handshake
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Enable UDP GSO on the client so that the burst is sent as a single super-packet
+    let segment_size: libc::c_int = 128;
+    let segment_size_ptr = (&segment_size as *const libc::c_int).cast();
+    let segment_size_len = size_of::<libc::c_int>() as libc::socklen_t;
+    // Safety: The option value is valid for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(client.as_raw_fd(), libc::SOL_UDP, libc::UDP_SEGMENT, segment_size_ptr, segment_size_len)
+    };
+    assert_eq!(result, 0, "failed to enable UDP GSO on client socket");
+
+    // Send a burst of packets as a single super-packet
+    let mut messages: Vec<Vec<u8>> = (0..16u8).map(|index| vec![index; 128]).collect();
+    messages.push(vec![0xFF; 50]);
+    client.send_to(&messages.concat(), wgproxy).expect("failed to send test packet");
+
+    // Ensure that all packets arrive as individual datagrams
+    for message in &messages {
+        let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+        assert_eq!(&buf[..buf_len], message);
+    }
+}
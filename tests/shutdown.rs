@@ -0,0 +1,61 @@
+//! Shutdown-related test cases
+//!
+//! # Note
+//! Termination requests are process-global, so these tests live in their own test binary.
+
+mod utils;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tests that a termination request drains existing sessions, rejects new ones, and exits gracefully
+#[test]
+pub fn drain() {
+    // Create custom proxy config with a unique port for this test file
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:61000".parse().expect("invalid listening address");
+        config.WGPROXY_TIMEOUT_UPLINK = Duration::from_secs(30);
+        config.WGPROXY_TIMEOUT_DOWNLINK = Duration::from_secs(30);
+        config.WGPROXY_MAX_SESSIONS = 4;
+        config.WGPROXY_DRAIN = Duration::from_secs(3);
+    });
+    server.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set server read timeout");
+
+    // Boot the relay
+    let config_ = config.clone();
+    let eventloop = thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Setup clients
+    let client0 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let client1 = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    client0.set_read_timeout(Some(Duration::from_secs(1))).expect("failed to set client read timeout");
+    let mut buf = [0; 512];
+
+    // Do handshake
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client0.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+
+    // Request termination
+    let terminated = Instant::now();
+    wgproxy::signal::terminate();
+    thread::sleep(Duration::from_millis(500));
+
+    // New sessions must be rejected
+    let handshake1 = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    client1.send_to(&handshake1, wgproxy).expect("failed to send test packet");
+    server.recv_from(&mut buf).expect_err("unexpected handshake while draining");
+
+    // The existing session must still be forwarded
+    let response = utils::response(&handshake, b"TESTOLOPE");
+    server.send_to(&response, wgproxy).expect("failed to send test reply");
+    let (buf_len, _) = client0.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], response);
+
+    // The relay must exit gracefully after the drain period
+    let result = eventloop.join().expect("eventloop has panicked");
+    assert!(result.is_ok(), "eventloop has failed");
+    assert!(terminated.elapsed() >= config.WGPROXY_DRAIN, "eventloop did not drain");
+}
@@ -0,0 +1,60 @@
+//! systemd-related test cases
+//!
+//! # Note
+//! The environment is process-global, so these tests live in their own test binary.
+
+mod utils;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+/// Receives notifications until the expected one arrives
+fn expect_notification(socket: &UnixDatagram, expected: &str) {
+    let mut buf = [0; 512];
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        let buf_len = socket.recv(&mut buf).expect("failed to receive notification");
+        if &buf[..buf_len] == expected.as_bytes() {
+            return;
+        }
+    }
+    panic!("missing notification {expected:?}");
+}
+
+/// Tests that the relay notifies systemd about its readiness, status and liveness
+#[test]
+#[cfg(target_os = "linux")]
+pub fn notify() {
+    let path = env::temp_dir().join(format!("wgproxy-notify-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    let notifications = UnixDatagram::bind(&path).expect("failed to create notification socket");
+    notifications.set_read_timeout(Some(Duration::from_secs(5))).expect("failed to set notification read timeout");
+
+    // Safety: There are no other threads that access the environment yet
+    unsafe {
+        env::set_var("NOTIFY_SOCKET", &path);
+        env::set_var("WATCHDOG_USEC", "1000000");
+        env::set_var("WATCHDOG_PID", process::id().to_string());
+    }
+
+    // Create custom proxy config with a unique port for this test file and boot the relay
+    let (config, wgproxy, server) = utils::config_with(|config| {
+        config.WGPROXY_LISTEN = "127.0.0.1:64000".parse().expect("invalid listening address");
+    });
+    let config_ = config.clone();
+    thread::spawn(move || wgproxy::eventloop(config_));
+    expect_notification(&notifications, "READY=1");
+    expect_notification(&notifications, "STATUS=Forwarding 0 sessions");
+    expect_notification(&notifications, "WATCHDOG=1");
+
+    // Do handshake and expect a status update
+    let client = UdpSocket::bind("127.0.0.1:0").expect("failed to create client socket");
+    let handshake = utils::handshake(&config.WGPROXY_PUBKEY[0].key);
+    let mut buf = [0; 512];
+    client.send_to(&handshake, wgproxy).expect("failed to send test packet");
+    let (buf_len, _) = server.recv_from(&mut buf).expect("failed to receive test packet");
+    assert_eq!(&buf[..buf_len], handshake);
+    expect_notification(&notifications, "STATUS=Forwarding 1 sessions");
+    let _ = fs::remove_file(&path);
+}
@@ -1,4 +1,5 @@
 //! Testing utils
+#![allow(dead_code, reason = "Not every test file uses every util")]
 
 use blake2::digest::Mac;
 use blake2::digest::consts::U16;
@@ -8,7 +9,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::thread;
 use std::time::Duration;
-use wgproxy::config::Config;
+use wgproxy::config::{Backend, Config, EvictionPolicy, IdlePolicy, PublicKey, Sandbox, TakeoverPolicy};
 
 /// The testing public key
 pub const WGPROXY_PUBKEY: [u8; 32] = hex!("4B6172696E6D6167656E20 4B6172696E6D6167656E20 4B6172696E6D6167656E");
@@ -17,6 +18,30 @@ pub const WGPROXY_BASEPORT: u16 = 60000;
 
 /// Starts a new separate [`wgproxy::eventloop`] session for testing
 pub fn session() -> (Config, SocketAddr, UdpSocket) {
+    session_with(|_| ())
+}
+
+/// Starts a new separate [`wgproxy::eventloop`] session for testing with a customized config
+pub fn session_with<F>(customize: F) -> (Config, SocketAddr, UdpSocket)
+where
+    F: FnOnce(&mut Config),
+{
+    let (config, proxy_address, server_socket) = config_with(customize);
+
+    // Boot the relay
+    let config_ = config.clone();
+    thread::spawn(move || wgproxy::eventloop(config_));
+    thread::sleep(Duration::from_secs(3));
+
+    // Return triple
+    (config, proxy_address, server_socket)
+}
+
+/// Creates a customized config for a new [`wgproxy::eventloop`] session without starting it
+pub fn config_with<F>(customize: F) -> (Config, SocketAddr, UdpSocket)
+where
+    F: FnOnce(&mut Config),
+{
     /// Atomic port counter to allocate unique UDP ports
     static PORT_COUNTER: AtomicU16 = AtomicU16::new(WGPROXY_BASEPORT);
 
@@ -27,20 +52,45 @@ pub fn session() -> (Config, SocketAddr, UdpSocket) {
     // Create config with socket addresses
     let proxy_port = PORT_COUNTER.fetch_add(1, Ordering::SeqCst);
     let proxy_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), proxy_port);
-    let config = Config {
+    let mut config = Config {
         WGPROXY_SERVER: server_address.to_string(),
-        WGPROXY_PUBKEY,
+        WGPROXY_PUBKEY: vec![PublicKey::new(WGPROXY_PUBKEY)],
+        WGPROXY_WG_CONFIG: None,
         WGPROXY_LISTEN: proxy_address,
+        WGPROXY_ALLOW: Vec::new(),
+        WGPROXY_DENY: Vec::new(),
         WGPROXY_TIMEOUT: Duration::from_secs(3),
+        WGPROXY_TIMEOUT_UPLINK: Duration::from_secs(3),
+        WGPROXY_TIMEOUT_DOWNLINK: Duration::from_secs(3),
+        WGPROXY_IDLE_POLICY: IdlePolicy::Min,
+        WGPROXY_REKEY_GRACE: None,
         WGPROXY_LOGLEVEL: 1,
+        WGPROXY_AMPLIFICATION_LIMIT: 0,
+        WGPROXY_AMPLIFICATION_RATIO: 3,
+        WGPROXY_REPLAY_WINDOW: Duration::from_secs(300),
+        WGPROXY_REPLAY_CAPACITY: 262144,
+        WGPROXY_TAKEOVER: TakeoverPolicy::Never,
+        WGPROXY_MAX_SESSIONS: 1,
+        WGPROXY_EVICTION: EvictionPolicy::Reject,
+        WGPROXY_MAX_SESSIONS_PER_PREFIX: 0,
+        WGPROXY_PREFIX_V4: 24,
+        WGPROXY_PREFIX_V6: 64,
+        WGPROXY_BACKEND: Backend::Auto,
+        WGPROXY_BATCH_SIZE: 32,
+        WGPROXY_OFFLOAD: false,
+        WGPROXY_WORKERS: 1,
+        WGPROXY_STATE: None,
+        WGPROXY_STATE_INTERVAL: Duration::from_secs(60),
+        WGPROXY_DRAIN: Duration::from_secs(5),
+        WGPROXY_HANDOVER: None,
+        WGPROXY_USER: None,
+        WGPROXY_GROUP: None,
+        WGPROXY_SANDBOX: Sandbox::Off,
     };
+    customize(&mut config);
 
-    // Boot the relay
-    let config_ = config.clone();
-    thread::spawn(move || wgproxy::eventloop(config_));
-    thread::sleep(Duration::from_secs(3));
-
-    // Return triple
+    // Return triple with the possibly customized listening address
+    let proxy_address = config.WGPROXY_LISTEN;
     (config, proxy_address, server_socket)
 }
 
@@ -58,10 +108,11 @@ pub fn handshake(public_key: &[u8; 32]) -> [u8; 148] {
     /// Counter to ensure unique handshakes
     static HANDSHAKE_COUNTER: AtomicU16 = AtomicU16::new(0);
 
-    // Set the packet number
+    // Set the packet number and the sender index
     let mut packet = TEMPLATE;
     let counter = HANDSHAKE_COUNTER.fetch_add(1, Ordering::SeqCst);
     packet[114..116].copy_from_slice(&counter.to_be_bytes());
+    packet[4..6].copy_from_slice(&counter.to_be_bytes());
 
     // Compute MAC1 over the packet
     let label_pubkey_hash = Blake2s256::new().chain_update(b"mac1----").chain_update(public_key).finalize();
@@ -71,3 +122,27 @@ pub fn handshake(public_key: &[u8; 32]) -> [u8; 148] {
     packet[116..132].copy_from_slice(&mac1.into_bytes());
     packet
 }
+
+/// Creates a server transport packet addressed to the sender index of the given handshake packet
+pub fn transport(handshake: &[u8; 148], payload: &[u8]) -> Vec<u8> {
+    let mut packet = b"\x04\x00\x00\x00".to_vec();
+    packet.extend_from_slice(&handshake[4..8]);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Creates a server handshake response packet addressed to the sender index of the given handshake packet
+pub fn response(handshake: &[u8; 148], payload: &[u8]) -> Vec<u8> {
+    let mut packet = b"\x02\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    packet.extend_from_slice(&handshake[4..8]);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Creates a client transport packet addressed to the sender index of the given server handshake response packet
+pub fn client_transport(response: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut packet = b"\x04\x00\x00\x00".to_vec();
+    packet.extend_from_slice(&response[4..8]);
+    packet.extend_from_slice(payload);
+    packet
+}
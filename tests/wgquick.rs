@@ -0,0 +1,67 @@
+//! wg-quick-config-related test cases
+
+mod utils;
+use std::collections::BTreeMap;
+use std::{env, fs, process};
+use wgproxy::config::{Config, PublicKey};
+use wgproxy::wgquick::{self, Peer};
+
+/// A wg-quick client config
+const WG_CONFIG: &str = "[Interface]
+PrivateKey = aGVsbG8gd29ybGQgaGVsbG8gd29ybGQgaGVsbG8gd28=
+Address = 10.0.0.2/32
+
+# The server
+[Peer]
+publickey = S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=
+AllowedIPs = 0.0.0.0/0
+Endpoint = 127.0.0.1:51820 # via the internet
+";
+
+/// Tests that the peer is extracted from a wg-quick config
+#[test]
+pub fn peer() {
+    let peer = Peer::parse(WG_CONFIG).expect("failed to parse WireGuard config");
+    assert_eq!(peer.public_key, "S2FyaW5tYWdlbiBLYXJpbm1hZ2VuIEthcmlubWFnZW4=");
+    assert_eq!(peer.endpoint.as_deref(), Some("127.0.0.1:51820"));
+
+    // Configs without a single peer must be rejected
+    let error = Peer::parse("[Interface]\n").expect_err("unexpected valid config");
+    assert_eq!(error.error, "Missing [Peer] section");
+    let error = Peer::parse(&format!("{WG_CONFIG}\n[Peer]\nPublicKey = x\n")).expect_err("unexpected valid config");
+    assert_eq!(error.error, "Expected a single [Peer] section, found 2");
+}
+
+/// Tests that the server address and public key are derived from a wg-quick config unless they are set explicitly
+#[test]
+pub fn config() {
+    let path = env::temp_dir().join(format!("wgproxy-wgquick-{}.conf", process::id()));
+    fs::write(&path, WG_CONFIG).expect("failed to write WireGuard config");
+
+    // Derive the server address and public key
+    let mut values = BTreeMap::new();
+    values.insert("WGPROXY_WG_CONFIG".to_string(), path.display().to_string());
+    let config = Config::from_values(&values).expect("failed to load config");
+    assert_eq!(config.WGPROXY_SERVER, "127.0.0.1:51820");
+    assert_eq!(config.WGPROXY_PUBKEY, vec![PublicKey::new(utils::WGPROXY_PUBKEY)]);
+
+    // Explicit values take precedence
+    values.insert("WGPROXY_SERVER".to_string(), "127.0.0.1:51821".to_string());
+    let config = Config::from_values(&values).expect("failed to load config");
+    assert_eq!(config.WGPROXY_SERVER, "127.0.0.1:51821");
+    let _ = fs::remove_file(&path);
+}
+
+/// Tests that the endpoint is rewritten and everything else is kept
+#[test]
+pub fn rewrite_endpoint() {
+    let rewritten = wgquick::rewrite_endpoint(WG_CONFIG, "relay.invalid:51820").expect("failed to rewrite config");
+    let expected = WG_CONFIG.replace("Endpoint = 127.0.0.1:51820 # via the internet", "Endpoint = relay.invalid:51820");
+    assert_eq!(rewritten, expected);
+
+    // A missing endpoint must be added to the peer
+    let config = WG_CONFIG.replace("Endpoint = 127.0.0.1:51820 # via the internet\n", "");
+    let rewritten = wgquick::rewrite_endpoint(&config, "relay.invalid:51820").expect("failed to rewrite config");
+    let expected = config.replace("[Peer]\n", "[Peer]\nEndpoint = relay.invalid:51820\n");
+    assert_eq!(rewritten, expected);
+}